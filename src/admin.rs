@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::card::Card;
+
+/// What happens to the next card scanned in admin mode.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminAction {
+    /// Binds a new card to what is currently playing, or adds it unbound.
+    ///
+    /// Cards that are already bound are refused, so a stray scan can't overwrite them.
+    Enroll,
+    Delete,
+    /// Turns the card into the given control card.
+    Control(Card),
+}
+
+impl AdminAction {
+    /// The actions in the order the admin card cycles through them.
+    #[must_use]
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::Enroll,
+            Self::Delete,
+            Self::Control(Card::Pause),
+            Self::Control(Card::Resume),
+            Self::Control(Card::Next),
+            Self::Control(Card::Previous),
+            Self::Control(Card::Shuffle),
+            Self::Control(Card::VolumeUp),
+            Self::Control(Card::VolumeDown),
+            Self::Control(Card::ToggleHotspot),
+        ]
+    }
+
+    /// Returns a name for the action, e.g. for the file of its prompt.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Enroll => "enroll",
+            Self::Delete => "delete",
+            Self::Control(Card::Pause) => "pause",
+            Self::Control(Card::Resume) => "resume",
+            Self::Control(Card::Next) => "next",
+            Self::Control(Card::Previous) => "previous",
+            Self::Control(Card::Shuffle) => "shuffle",
+            Self::Control(Card::VolumeUp) => "volume_up",
+            Self::Control(Card::VolumeDown) => "volume_down",
+            Self::Control(Card::ToggleHotspot) => "toggle_hotspot",
+            Self::Control(_) => "control",
+        }
+    }
+}
+
+/// The admin mode entered by scanning an admin card.
+///
+/// Every further scan of the admin card selects the next action and restarts the window.
+#[derive(Debug)]
+pub struct Admin {
+    actions: Vec<AdminAction>,
+    selected: usize,
+    window: Duration,
+    deadline: Instant,
+}
+
+impl Admin {
+    /// Enters admin mode with the first action selected, or returns `None` if `window` is too
+    /// long to represent.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - How long admin mode waits for the next scan.
+    #[must_use]
+    pub fn new(window: Duration) -> Option<Self> {
+        Some(Self {
+            actions: AdminAction::all(),
+            selected: 0,
+            window,
+            deadline: Instant::now().checked_add(window)?,
+        })
+    }
+
+    #[must_use]
+    pub fn action(&self) -> &AdminAction {
+        &self.actions[self.selected]
+    }
+
+    /// Selects the next action and restarts the window.
+    pub fn cycle(&mut self) {
+        self.selected = (self.selected + 1) % self.actions.len();
+        if let Some(deadline) = Instant::now().checked_add(self.window) {
+            self.deadline = deadline;
+        }
+    }
+
+    #[must_use]
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
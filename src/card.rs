@@ -1,10 +1,12 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Card {
     Play(Arc<str>),
+    Playlist(Vec<Arc<str>>),
+    Audiobook(Arc<str>),
     Pause,
     Resume,
     Next,
@@ -13,6 +15,8 @@ pub enum Card {
     VolumeUp,
     VolumeDown,
     ToggleHotspot,
+    SleepTimer(Duration),
+    Admin,
 }
 
 impl From<&Card> for Option<Card> {
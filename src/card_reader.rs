@@ -1,70 +1,342 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
 use crossbeam_channel::Sender;
-use rusb::UsbContext;
-use tracing::{debug, error};
+use serde::Serialize;
+use tracing::{debug, info, warn};
 
 use crate::error::Error;
 
-fn extract_card_id(buf: &[u8]) -> Option<String> {
-    let significant_indices = [2, 18, 34, 50, 66, 82, 98, 114];
-    let extracted: Vec<u8> = significant_indices
-        .iter()
-        .filter_map(|&i| buf.get(i).copied())
-        .collect();
-
-    if extracted.len() == 8 && extracted.iter().all(|&b| b != 0) {
-        Some(extracted.iter().fold(String::new(), |mut acc, &b| {
-            acc.push_str(&format!("{b:02X}"));
-            acc
-        }))
-    } else {
-        None
-    }
+mod decoder;
+mod evdev;
+mod simulated;
+mod usb;
+
+pub use decoder::{DecoderKind, KeyboardWedge, RawUid, ReportDecoder, SparseReport};
+pub use evdev::EvdevReader;
+pub use simulated::SimulatedReader;
+pub use usb::{UsbId, UsbReader};
+
+/// How long readers wait before looking for a lost device again, doubling on every attempt.
+static MIN_BACKOFF: Duration = Duration::from_secs(1);
+static MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A change of the card on a reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CardEvent {
+    /// A card was placed on the reader.
+    Present(Arc<str>),
+    /// The card was lifted from the reader.
+    Removed(Arc<str>),
+    /// The reader was connected or lost.
+    Reader(ReaderStatus),
 }
 
-/// Reads data from the card reader.
-///
-/// # Arguments
-///
-/// * `vid` - The vendor ID.
-/// * `pid` - The product ID.
-/// * `tx` - The sender channel for sending the card ID.
-///
-/// # Errors
-///
-/// Returns an `Error` if there was an error reading from the card reader or sending the card ID.
-pub fn read(vid: u16, pid: u16, tx: &Sender<Arc<str>>) -> Result<(), Error> {
-    let context = rusb::Context::new()?;
-    let handle = context
-        .open_device_with_vid_pid(vid, pid)
-        .ok_or(Error::Rusb(rusb::Error::NotFound))?;
-
-    #[cfg(target_os = "linux")]
-    {
-        if handle.kernel_driver_active(0)? {
-            handle.detach_kernel_driver(0)?;
-        }
+/// Whether a card reader is available.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ReaderStatus {
+    /// No reader was found yet.
+    Searching,
+    Connected {
+        device: Arc<str>,
+    },
+    /// The reader was lost and is being looked for again.
+    Disconnected,
+}
+
+/// A source of card IDs.
+pub trait CardReader {
+    /// Reads cards and sends their events on `tx` until the reader is exhausted.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - The sender channel for sending the card events.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if there was an error reading from the card reader or sending an event.
+    fn read(&mut self, tx: &Sender<CardEvent>) -> Result<(), Error>;
+}
+
+fn send_status(tx: &Sender<CardEvent>, status: ReaderStatus) -> Result<(), Error> {
+    tx.send(CardEvent::Reader(status)).map_err(Error::Send)
+}
+
+/// A device a reader reads cards from, which can be lost and opened again.
+trait ReaderDevice {
+    type Handle;
+
+    /// Opens the device and returns its name for the service and a handle to read it with.
+    fn open(&mut self) -> Result<(Arc<str>, Self::Handle), Error>;
+
+    /// Reads cards from the opened device until it ends or fails.
+    fn read_cards(
+        &mut self,
+        handle: Self::Handle,
+        presence: &mut Presence,
+        tx: &Sender<CardEvent>,
+    ) -> Result<(), Error>;
+
+    /// Returns whether the device can be opened again after it failed.
+    fn reopens(&self) -> bool {
+        true
     }
 
-    handle.claim_interface(0)?;
+    /// Blocks for `timeout` before the device is opened again.
+    fn wait(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
 
-    let mut buf = [0; 128];
-    let mut last_scan = std::time::Instant::now();
-    debug!("Card reader ready");
+/// Reads cards from `device` until it ends, reporting it to the service and opening it again
+/// with a backoff whenever it is missing or fails.
+fn read_reconnecting<D: ReaderDevice>(
+    device: &mut D,
+    presence: &mut Presence,
+    tx: &Sender<CardEvent>,
+) -> Result<(), Error> {
+    let mut backoff = MIN_BACKOFF;
+    // Whether the service was told about the reader at all.
+    let mut reported = false;
     loop {
-        match handle.read_interrupt(0x81, &mut buf, Duration::from_secs(1)) {
-            Ok(_) => {
-                if let Some(card_id) = extract_card_id(&buf) {
-                    if last_scan.elapsed() < Duration::from_secs(2) {
-                        continue;
+        match device.open() {
+            Ok((name, handle)) => {
+                info!("Card reader {name} connected");
+                send_status(
+                    tx,
+                    ReaderStatus::Connected {
+                        device: name.clone(),
+                    },
+                )?;
+                let connected_at = Instant::now();
+                match device.read_cards(handle, presence, tx) {
+                    Ok(()) => {
+                        debug!("Card reader {name} exhausted");
+                        return Ok(());
                     }
-                    tx.send(Arc::from(card_id)).map_err(Error::Send)?;
-                    last_scan = std::time::Instant::now();
+                    Err(Error::Send(err)) => return Err(Error::Send(err)),
+                    Err(err) if !device.reopens() => return Err(err),
+                    Err(err) => warn!("Card reader {name} disconnected: {err}"),
+                }
+                presence.remove(tx)?;
+                send_status(tx, ReaderStatus::Disconnected)?;
+                reported = true;
+                // Keeps backing off from a device that fails right after it was opened.
+                if connected_at.elapsed() > MAX_BACKOFF {
+                    backoff = MIN_BACKOFF;
                 }
             }
-            Err(rusb::Error::Timeout) => (),
-            Err(e) => error!("Error: {e}"),
+            // A lost reader stays disconnected until it is found again.
+            Err(err) if !reported => {
+                warn!("Waiting for a card reader: {err}");
+                send_status(tx, ReaderStatus::Searching)?;
+                reported = true;
+            }
+            Err(err) => debug!("Failed to open the card reader: {err}"),
+        }
+        device.wait(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Tracks the card on a reader that reports it repeatedly while it is present.
+///
+/// A card counts as removed once it hasn't been reported for the removal timeout. Readers that
+/// only report a card once therefore see it removed shortly after every scan. A card that is
+/// placed again within the debounce time of its last announcement is tracked silently.
+pub struct Presence {
+    card: Option<Arc<str>>,
+    /// Whether the present card was announced, or debounced.
+    announced: bool,
+    last_seen: Instant,
+    removal_timeout: Duration,
+    debounce: Duration,
+    last_announced: HashMap<Arc<str>, Instant>,
+}
+
+impl Presence {
+    /// Creates a tracker for a reader with no card on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `removal_timeout` - How long a card may go unreported before it counts as removed.
+    /// * `debounce` - How long after its announcement a card is not announced again.
+    #[must_use]
+    pub fn new(removal_timeout: Duration, debounce: Duration) -> Self {
+        Self {
+            card: None,
+            announced: false,
+            last_seen: Instant::now(),
+            removal_timeout,
+            debounce,
+            last_announced: HashMap::new(),
+        }
+    }
+
+    /// Records a report of `card_id` and sends the events it causes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if an event could not be sent.
+    pub fn seen(&mut self, card_id: Arc<str>, tx: &Sender<CardEvent>) -> Result<(), Error> {
+        self.last_seen = Instant::now();
+        if self.card.as_ref() == Some(&card_id) {
+            return Ok(());
+        }
+        self.remove(tx)?;
+        let debounce = self.debounce;
+        self.last_announced.retain(|_, at| at.elapsed() < debounce);
+        self.announced = !self.last_announced.contains_key(&card_id);
+        self.card = Some(card_id.clone());
+        if self.announced {
+            self.last_announced.insert(card_id.clone(), Instant::now());
+            tx.send(CardEvent::Present(card_id)).map_err(Error::Send)
+        } else {
+            debug!("Debounced card {card_id}");
+            Ok(())
+        }
+    }
+
+    /// Sends a removal if the card hasn't been reported within the removal timeout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if the event could not be sent.
+    pub fn check(&mut self, tx: &Sender<CardEvent>) -> Result<(), Error> {
+        if self.last_seen.elapsed() < self.removal_timeout {
+            return Ok(());
+        }
+        self.remove(tx)
+    }
+
+    /// Sends a removal for the present card, e.g. because the reader was lost.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if the event could not be sent.
+    pub fn remove(&mut self, tx: &Sender<CardEvent>) -> Result<(), Error> {
+        match self.card.take() {
+            Some(card_id) if std::mem::take(&mut self.announced) => {
+                tx.send(CardEvent::Removed(card_id)).map_err(Error::Send)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG: Duration = Duration::from_secs(3600);
+
+    fn events(rx: &crossbeam_channel::Receiver<CardEvent>) -> Vec<CardEvent> {
+        rx.try_iter().collect()
+    }
+
+    #[test]
+    fn repeated_reports_announce_the_card_once() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut presence = Presence::new(LONG, Duration::ZERO);
+        for _ in 0..3 {
+            presence.seen("A".into(), &tx).unwrap();
+            presence.check(&tx).unwrap();
         }
+        assert_eq!(events(&rx), [CardEvent::Present("A".into())]);
+    }
+
+    #[test]
+    fn card_is_removed_after_the_timeout() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut presence = Presence::new(Duration::from_millis(20), Duration::ZERO);
+        presence.seen("A".into(), &tx).unwrap();
+        presence.check(&tx).unwrap();
+        assert_eq!(events(&rx), [CardEvent::Present("A".into())]);
+        std::thread::sleep(Duration::from_millis(30));
+        presence.check(&tx).unwrap();
+        presence.check(&tx).unwrap();
+        assert_eq!(events(&rx), [CardEvent::Removed("A".into())]);
+    }
+
+    #[test]
+    fn another_card_replaces_the_present_one() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut presence = Presence::new(LONG, Duration::ZERO);
+        presence.seen("A".into(), &tx).unwrap();
+        presence.seen("B".into(), &tx).unwrap();
+        assert_eq!(
+            events(&rx),
+            [
+                CardEvent::Present("A".into()),
+                CardEvent::Removed("A".into()),
+                CardEvent::Present("B".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn card_placed_again_within_the_debounce_is_silent() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut presence = Presence::new(Duration::ZERO, LONG);
+        presence.seen("A".into(), &tx).unwrap();
+        presence.check(&tx).unwrap();
+        presence.seen("A".into(), &tx).unwrap();
+        presence.check(&tx).unwrap();
+        assert_eq!(
+            events(&rx),
+            [
+                CardEvent::Present("A".into()),
+                CardEvent::Removed("A".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn debounce_is_per_card() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut presence = Presence::new(LONG, LONG);
+        presence.seen("A".into(), &tx).unwrap();
+        presence.seen("B".into(), &tx).unwrap();
+        presence.seen("A".into(), &tx).unwrap();
+        assert_eq!(
+            events(&rx),
+            [
+                CardEvent::Present("A".into()),
+                CardEvent::Removed("A".into()),
+                CardEvent::Present("B".into()),
+                CardEvent::Removed("B".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn card_is_announced_again_after_the_debounce() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut presence = Presence::new(Duration::ZERO, Duration::from_millis(20));
+        presence.seen("A".into(), &tx).unwrap();
+        presence.remove(&tx).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        presence.seen("A".into(), &tx).unwrap();
+        assert_eq!(
+            events(&rx),
+            [
+                CardEvent::Present("A".into()),
+                CardEvent::Removed("A".into()),
+                CardEvent::Present("A".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_without_a_card_sends_nothing() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut presence = Presence::new(LONG, LONG);
+        presence.remove(&tx).unwrap();
+        assert!(events(&rx).is_empty());
     }
 }
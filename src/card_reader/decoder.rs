@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+
+/// The HID usage IDs of the keys a keyboard-wedge reader types.
+const KEY_A: u8 = 0x04;
+const KEY_Z: u8 = 0x1d;
+const KEY_1: u8 = 0x1e;
+const KEY_0: u8 = 0x27;
+const KEY_ENTER: u8 = 0x28;
+const KEYPAD_ENTER: u8 = 0x58;
+
+/// The length of a HID boot keyboard report.
+const KEYBOARD_REPORT_LEN: usize = 8;
+
+/// Turns the reports of a USB reader into card IDs.
+pub trait ReportDecoder: Send {
+    /// Feeds one report read from the device.
+    ///
+    /// Returns the card ID once the reports so far contain a complete one.
+    fn decode(&mut self, report: &[u8]) -> Option<String>;
+}
+
+/// Selects how the reports of a reader are decoded.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum DecoderKind {
+    #[default]
+    SparseReport,
+    KeyboardWedge,
+    RawUid,
+}
+
+impl DecoderKind {
+    #[must_use]
+    pub fn build(self) -> Box<dyn ReportDecoder> {
+        match self {
+            Self::SparseReport => Box::new(SparseReport),
+            Self::KeyboardWedge => Box::new(KeyboardWedge::default()),
+            Self::RawUid => Box::new(RawUid),
+        }
+    }
+}
+
+/// Reads the 8 bytes at every 16th position of a 128-byte report, starting at index 2.
+///
+/// This is the layout of the reader the box was built with. The ID is the hex representation
+/// of those bytes.
+pub struct SparseReport;
+
+impl ReportDecoder for SparseReport {
+    fn decode(&mut self, report: &[u8]) -> Option<String> {
+        let significant_indices = [2, 18, 34, 50, 66, 82, 98, 114];
+        let extracted: Vec<u8> = significant_indices
+            .iter()
+            .filter_map(|&i| report.get(i).copied())
+            .collect();
+
+        if extracted.len() == 8 && extracted.iter().all(|&b| b != 0) {
+            Some(to_hex(&extracted))
+        } else {
+            None
+        }
+    }
+}
+
+/// Collects the keys typed by a reader that acts as a keyboard until it presses Enter.
+///
+/// Reports are HID boot keyboard reports, possibly several in one transfer. Digits and letters
+/// make up the ID, with letters in upper case.
+#[derive(Default)]
+pub struct KeyboardWedge {
+    typed: String,
+    pressed: u8,
+}
+
+impl ReportDecoder for KeyboardWedge {
+    fn decode(&mut self, report: &[u8]) -> Option<String> {
+        let mut card_id = None;
+        for keyboard_report in report.chunks(KEYBOARD_REPORT_LEN) {
+            let key = keyboard_report.get(2).copied().unwrap_or(0);
+            // A key stays in the reports until it is released, so only count it once.
+            if key == self.pressed {
+                continue;
+            }
+            self.pressed = key;
+            match key {
+                KEY_1..KEY_0 => self.typed.push(char::from(b'1' + key - KEY_1)),
+                KEY_0 => self.typed.push('0'),
+                KEY_A..=KEY_Z => self.typed.push(char::from(b'A' + key - KEY_A)),
+                KEY_ENTER | KEYPAD_ENTER if !self.typed.is_empty() => {
+                    card_id = Some(std::mem::take(&mut self.typed));
+                }
+                _ => {}
+            }
+        }
+        card_id
+    }
+}
+
+/// Reads a 4, 7 or 10-byte UID from the start of a single report.
+///
+/// The report may be padded with zeros, so UIDs ending in a zero byte are not supported.
+pub struct RawUid;
+
+impl ReportDecoder for RawUid {
+    fn decode(&mut self, report: &[u8]) -> Option<String> {
+        let len = report.iter().rposition(|&b| b != 0)? + 1;
+        matches!(len, 4 | 7 | 10).then(|| to_hex(&report[..len]))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut acc, &b| {
+        acc.push_str(&format!("{b:02X}"));
+        acc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A transfer of the original reader: each byte of the ID in a key press report, followed
+    /// by a release report.
+    const SPARSE_TRANSFER: [u8; 128] = {
+        let id = [0x04, 0x1e, 0x27, 0x28, 0x05, 0x1f, 0x21, 0x22];
+        let mut transfer = [0; 128];
+        let mut i = 0;
+        while i < id.len() {
+            transfer[2 + 16 * i] = id[i];
+            i += 1;
+        }
+        transfer
+    };
+
+    const RELEASE: [u8; 8] = [0; 8];
+
+    fn press(key: u8) -> [u8; 8] {
+        [0, 0, key, 0, 0, 0, 0, 0]
+    }
+
+    fn feed(decoder: &mut dyn ReportDecoder, reports: &[[u8; 8]]) -> Vec<String> {
+        reports
+            .iter()
+            .filter_map(|report| decoder.decode(report))
+            .collect()
+    }
+
+    #[test]
+    fn sparse_report_decodes_a_full_transfer() {
+        assert_eq!(
+            SparseReport.decode(&SPARSE_TRANSFER).as_deref(),
+            Some("041E2728051F2122")
+        );
+    }
+
+    #[test]
+    fn sparse_report_rejects_zero_bytes() {
+        let mut transfer = SPARSE_TRANSFER;
+        transfer[50] = 0;
+        assert_eq!(SparseReport.decode(&transfer), None);
+        assert_eq!(SparseReport.decode(&[0; 128]), None);
+    }
+
+    #[test]
+    fn sparse_report_rejects_short_transfers() {
+        assert_eq!(SparseReport.decode(&SPARSE_TRANSFER[..64]), None);
+        assert_eq!(SparseReport.decode(&SPARSE_TRANSFER[..114]), None);
+        assert!(SparseReport.decode(&SPARSE_TRANSFER[..115]).is_some());
+    }
+
+    #[test]
+    fn keyboard_wedge_counts_repeated_keys_split_by_releases() {
+        let mut decoder = KeyboardWedge::default();
+        let reports = [
+            press(KEY_1),
+            RELEASE,
+            press(KEY_1),
+            RELEASE,
+            press(KEY_0),
+            RELEASE,
+            press(KEY_ENTER),
+            RELEASE,
+        ];
+        assert_eq!(feed(&mut decoder, &reports), ["110"]);
+    }
+
+    #[test]
+    fn keyboard_wedge_counts_held_keys_once() {
+        let mut decoder = KeyboardWedge::default();
+        let reports = [
+            press(KEY_A),
+            press(KEY_A),
+            press(KEY_A + 1),
+            press(KEYPAD_ENTER),
+        ];
+        assert_eq!(feed(&mut decoder, &reports), ["AB"]);
+    }
+
+    #[test]
+    fn keyboard_wedge_decodes_several_reports_per_transfer() {
+        let mut decoder = KeyboardWedge::default();
+        let transfer = [
+            press(KEY_1 + 1),
+            RELEASE,
+            press(KEY_1 + 1),
+            RELEASE,
+            press(KEY_Z),
+            RELEASE,
+            press(KEY_ENTER),
+            RELEASE,
+        ]
+        .concat();
+        assert_eq!(decoder.decode(&transfer).as_deref(), Some("22Z"));
+    }
+
+    #[test]
+    fn keyboard_wedge_continues_across_transfers() {
+        let mut decoder = KeyboardWedge::default();
+        assert_eq!(decoder.decode(&[press(KEY_1), RELEASE].concat()), None);
+        assert_eq!(decoder.decode(&[press(KEY_1), RELEASE].concat()), None);
+        assert_eq!(
+            decoder
+                .decode(&[press(KEY_ENTER), RELEASE].concat())
+                .as_deref(),
+            Some("11")
+        );
+    }
+
+    #[test]
+    fn keyboard_wedge_ignores_enter_without_an_id() {
+        let mut decoder = KeyboardWedge::default();
+        let reports = [press(KEY_ENTER), RELEASE, press(KEY_ENTER), RELEASE];
+        assert!(feed(&mut decoder, &reports).is_empty());
+    }
+
+    #[test]
+    fn raw_uid_decodes_padded_uids() {
+        let mut transfer = [0; 64];
+        transfer[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(RawUid.decode(&transfer).as_deref(), Some("DEADBEEF"));
+
+        let mut transfer = [0; 64];
+        transfer[..7].copy_from_slice(&[0x04, 0x91, 0x3a, 0x02, 0x5c, 0x6f, 0x80]);
+        assert_eq!(RawUid.decode(&transfer).as_deref(), Some("04913A025C6F80"));
+
+        let mut transfer = [0; 16];
+        transfer[..10].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(
+            RawUid.decode(&transfer).as_deref(),
+            Some("0102030405060708090A")
+        );
+    }
+
+    #[test]
+    fn raw_uid_rejects_other_lengths() {
+        assert_eq!(RawUid.decode(&[0; 64]), None);
+        assert_eq!(RawUid.decode(&[]), None);
+        assert_eq!(RawUid.decode(&[1, 2, 3, 4, 5, 0, 0, 0]), None);
+        assert_eq!(RawUid.decode(&[1; 11]), None);
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    os::{fd::AsRawFd, unix::fs::FileTypeExt},
+    sync::Arc,
+};
+
+use crossbeam_channel::Sender;
+use tracing::debug;
+
+use super::{read_reconnecting, CardEvent, CardReader, Presence, ReaderDevice};
+use crate::error::Error;
+
+/// The size of a `struct input_event`, whose last 8 bytes hold the type, code and value.
+const EVENT_SIZE: usize = std::mem::size_of::<libc::input_event>();
+const EV_KEY: u16 = 0x01;
+const KEY_PRESSED: i32 = 1;
+const KEY_ENTER: u16 = 28;
+const KEY_KPENTER: u16 = 96;
+/// `_IOW('E', 0x90, int)`
+const EVIOCGRAB: libc::c_ulong = 0x4004_4590;
+
+type Open<R> = Box<dyn FnMut() -> Result<R, Error> + Send>;
+
+/// A card reader that acts as a keyboard and types the card ID followed by Enter.
+///
+/// Reads `struct input_event`s from a `/dev/input/event*` device, or from a file with
+/// recorded events. A device that fails is opened again, so the reader can be unplugged.
+pub struct EvdevReader<R> {
+    input: Input<R>,
+    presence: Presence,
+}
+
+/// The device or recording a keyboard reader types into.
+struct Input<R> {
+    pending: Option<R>,
+    open: Option<Open<R>>,
+    device: Arc<str>,
+}
+
+impl<R: Read> EvdevReader<R> {
+    #[must_use]
+    pub fn new(input: R, presence: Presence) -> Self {
+        Self {
+            input: Input {
+                pending: Some(input),
+                open: None,
+                device: "evdev".into(),
+            },
+            presence,
+        }
+    }
+}
+
+impl EvdevReader<File> {
+    /// Creates a reader for an input device, which is grabbed so the typed IDs don't reach
+    /// other programs. Files with recorded events are read as they are.
+    ///
+    /// The device is opened once the reader runs, and waited for if it is missing.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the device, e.g. `/dev/input/event0`, or to a recording.
+    /// * `presence` - Decides when a card counts as placed or removed.
+    #[must_use]
+    pub fn open<P: AsRef<str>>(file_path: P, presence: Presence) -> Self {
+        let device: Arc<str> = file_path.as_ref().into();
+        Self {
+            input: Input {
+                pending: None,
+                open: Some(Box::new({
+                    let device = device.clone();
+                    move || open_device(&device)
+                })),
+                device,
+            },
+            presence,
+        }
+    }
+}
+
+impl<R: Read> CardReader for EvdevReader<R> {
+    fn read(&mut self, tx: &Sender<CardEvent>) -> Result<(), Error> {
+        read_reconnecting(&mut self.input, &mut self.presence, tx)
+    }
+}
+
+impl<R: Read> ReaderDevice for Input<R> {
+    type Handle = R;
+
+    fn open(&mut self) -> Result<(Arc<str>, R), Error> {
+        let input = match (self.pending.take(), self.open.as_mut()) {
+            (Some(input), _) => input,
+            (None, Some(open)) => open()?,
+            (None, None) => return Err(Error::Io(io::ErrorKind::NotFound.into())),
+        };
+        Ok((self.device.clone(), input))
+    }
+
+    /// Reads key events from `input` until it ends or fails.
+    fn read_cards(
+        &mut self,
+        mut input: R,
+        presence: &mut Presence,
+        tx: &Sender<CardEvent>,
+    ) -> Result<(), Error> {
+        let mut event = [0; EVENT_SIZE];
+        let mut typed = String::new();
+        loop {
+            match input.read_exact(&mut event) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(Error::Io(err)),
+            }
+            let Some((code, value)) = parse_key(&event) else {
+                continue;
+            };
+            if value != KEY_PRESSED {
+                continue;
+            }
+            match code {
+                KEY_ENTER | KEY_KPENTER if !typed.is_empty() => {
+                    // Keyboard readers only type a card once, so removals are only noticed here.
+                    presence.check(tx)?;
+                    presence.seen(Arc::from(std::mem::take(&mut typed)), tx)?;
+                }
+                code => typed.extend(key_char(code)),
+            }
+        }
+    }
+
+    fn reopens(&self) -> bool {
+        self.open.is_some()
+    }
+}
+
+/// Opens the input device at `file_path` and grabs it if it is a device.
+fn open_device(file_path: &str) -> Result<File, Error> {
+    let file = File::open(file_path)?;
+    if file.metadata()?.file_type().is_char_device() {
+        // SAFETY: The descriptor is open for the lifetime of `file` and EVIOCGRAB takes an int.
+        if unsafe { libc::ioctl(file.as_raw_fd(), EVIOCGRAB, 1) } != 0 {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+        debug!("Grabbed input device {file_path}");
+    }
+    Ok(file)
+}
+
+/// Returns the code and value of a key event.
+fn parse_key(event: &[u8; EVENT_SIZE]) -> Option<(u16, i32)> {
+    let [t0, t1, c0, c1, v0, v1, v2, v3] = event[EVENT_SIZE - 8..] else {
+        return None;
+    };
+    (u16::from_ne_bytes([t0, t1]) == EV_KEY).then(|| {
+        (
+            u16::from_ne_bytes([c0, c1]),
+            i32::from_ne_bytes([v0, v1, v2, v3]),
+        )
+    })
+}
+
+/// Maps the Linux key codes of digits and letters to the characters of a card ID.
+const fn key_char(code: u16) -> Option<char> {
+    Some(match code {
+        2 => '1',
+        3 => '2',
+        4 => '3',
+        5 => '4',
+        6 => '5',
+        7 => '6',
+        8 => '7',
+        9 => '8',
+        10 => '9',
+        11 => '0',
+        79 => '1',
+        80 => '2',
+        81 => '3',
+        75 => '4',
+        76 => '5',
+        77 => '6',
+        71 => '7',
+        72 => '8',
+        73 => '9',
+        82 => '0',
+        16 => 'Q',
+        17 => 'W',
+        18 => 'E',
+        19 => 'R',
+        20 => 'T',
+        21 => 'Y',
+        22 => 'U',
+        23 => 'I',
+        24 => 'O',
+        25 => 'P',
+        30 => 'A',
+        31 => 'S',
+        32 => 'D',
+        33 => 'F',
+        34 => 'G',
+        35 => 'H',
+        36 => 'J',
+        37 => 'K',
+        38 => 'L',
+        44 => 'Z',
+        45 => 'X',
+        46 => 'C',
+        47 => 'V',
+        48 => 'B',
+        49 => 'N',
+        50 => 'M',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, time::Duration};
+
+    use super::*;
+    use crate::card_reader::ReaderStatus;
+
+    const KEY_1: u16 = 2;
+    const KEY_2: u16 = 3;
+    const KEY_A: u16 = 30;
+    const KEY_B: u16 = 48;
+    const EV_SYN: u16 = 0x00;
+    const EV_MSC: u16 = 0x04;
+    const MSC_SCAN: u16 = 0x04;
+
+    fn event(kind: u16, code: u16, value: i32) -> [u8; EVENT_SIZE] {
+        let mut event = [0; EVENT_SIZE];
+        event[EVENT_SIZE - 8..EVENT_SIZE - 6].copy_from_slice(&kind.to_ne_bytes());
+        event[EVENT_SIZE - 6..EVENT_SIZE - 4].copy_from_slice(&code.to_ne_bytes());
+        event[EVENT_SIZE - 4..].copy_from_slice(&value.to_ne_bytes());
+        event
+    }
+
+    /// Records the events a keyboard reader sends when it types `keys` and Enter.
+    fn typed(keys: &[u16]) -> Vec<u8> {
+        keys.iter()
+            .chain(&[KEY_ENTER])
+            .flat_map(|&key| {
+                [
+                    event(EV_MSC, MSC_SCAN, 0x7001e),
+                    event(EV_KEY, key, 1),
+                    event(EV_SYN, 0, 0),
+                    event(EV_KEY, key, 0),
+                    event(EV_SYN, 0, 0),
+                ]
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Fails every read with `err`, or ends the input if there is none.
+    struct Unplugged(Option<io::ErrorKind>);
+
+    impl Read for Unplugged {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            self.0.map_or(Ok(0), |kind| Err(kind.into()))
+        }
+    }
+
+    fn presence() -> Presence {
+        Presence::new(Duration::ZERO, Duration::ZERO)
+    }
+
+    fn connected(device: &str) -> CardEvent {
+        CardEvent::Reader(ReaderStatus::Connected {
+            device: device.into(),
+        })
+    }
+
+    #[test]
+    fn recorded_events_are_read_as_cards() {
+        let recording = [
+            typed(&[KEY_1, KEY_2]),
+            typed(&[KEY_A, KEY_B]),
+            typed(&[KEY_A, KEY_B]),
+        ];
+        let mut reader = EvdevReader::new(Cursor::new(recording.concat()), presence());
+        let (tx, rx) = crossbeam_channel::unbounded();
+        reader.read(&tx).unwrap();
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            [
+                connected("evdev"),
+                CardEvent::Present("12".into()),
+                CardEvent::Removed("12".into()),
+                CardEvent::Present("AB".into()),
+                CardEvent::Removed("AB".into()),
+                CardEvent::Present("AB".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn enter_without_an_id_is_ignored() {
+        let mut reader = EvdevReader::new(Cursor::new(typed(&[])), presence());
+        let (tx, rx) = crossbeam_channel::unbounded();
+        reader.read(&tx).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [connected("evdev")]);
+    }
+
+    #[test]
+    fn read_errors_fail_without_a_device_to_reopen() {
+        let input = Cursor::new(typed(&[KEY_1])).chain(Unplugged(Some(io::ErrorKind::Other)));
+        let mut reader = EvdevReader::new(input, presence());
+        let (tx, _rx) = crossbeam_channel::unbounded();
+        assert!(matches!(reader.read(&tx), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn unplugged_device_is_opened_again() {
+        let unplugged = Cursor::new(typed(&[KEY_1])).chain(Unplugged(Some(io::ErrorKind::Other)));
+        let mut reader = EvdevReader::new(unplugged, presence());
+        reader.input.open = Some(Box::new(|| {
+            Ok(Cursor::new(typed(&[KEY_2])).chain(Unplugged(None)))
+        }));
+        let (tx, rx) = crossbeam_channel::unbounded();
+        reader.read(&tx).unwrap();
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            [
+                connected("evdev"),
+                CardEvent::Present("1".into()),
+                CardEvent::Removed("1".into()),
+                CardEvent::Reader(ReaderStatus::Disconnected),
+                connected("evdev"),
+                CardEvent::Present("2".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    os::unix::fs::FileTypeExt,
+    sync::Arc,
+    time::Duration,
+};
+
+use crossbeam_channel::Sender;
+use tracing::{debug, warn};
+
+use super::{CardEvent, CardReader, ReaderStatus};
+use crate::error::Error;
+
+/// A card reader that replays card IDs from a script instead of a USB device.
+///
+/// Every line holds the ID of a card placed on the reader, replacing the previous one, and
+/// `remove` lifts the card again. Empty lines and lines starting with `#` are ignored and
+/// `sleep <seconds>` pauses before the next line. This works for script files, FIFOs and stdin.
+///
+/// A FIFO is opened again whenever its writer closes it, so cards can be sent one at a time.
+pub struct SimulatedReader<R> {
+    input: R,
+    reopen: Option<Reopen<R>>,
+}
+
+type Reopen<R> = Box<dyn FnMut() -> io::Result<R> + Send>;
+
+impl<R: BufRead> SimulatedReader<R> {
+    #[must_use]
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            reopen: None,
+        }
+    }
+}
+
+impl SimulatedReader<BufReader<File>> {
+    /// Opens a script file or FIFO.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the script.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if the file could not be opened.
+    pub fn from_file<P: AsRef<str>>(file_path: P) -> Result<Self, Error> {
+        let file_path = file_path.as_ref().to_owned();
+        let mut reader = Self::new(BufReader::new(File::open(&file_path)?));
+        if fs::metadata(&file_path)?.file_type().is_fifo() {
+            reader.reopen = Some(Box::new(move || File::open(&file_path).map(BufReader::new)));
+        }
+        Ok(reader)
+    }
+}
+
+impl SimulatedReader<BufReader<io::Stdin>> {
+    #[must_use]
+    pub fn stdin() -> Self {
+        Self::new(BufReader::new(io::stdin()))
+    }
+}
+
+impl<R: BufRead> CardReader for SimulatedReader<R> {
+    fn read(&mut self, tx: &Sender<CardEvent>) -> Result<(), Error> {
+        debug!("Simulated card reader ready");
+        tx.send(CardEvent::Reader(ReaderStatus::Connected {
+            device: "simulated".into(),
+        }))
+        .map_err(Error::Send)?;
+        let mut line = String::new();
+        let mut present: Option<Arc<str>> = None;
+        loop {
+            line.clear();
+            if self.input.read_line(&mut line)? == 0 {
+                let Some(reopen) = self.reopen.as_mut() else {
+                    debug!("Simulated card reader exhausted");
+                    return Ok(());
+                };
+                // Blocks until the next writer opens the FIFO.
+                self.input = reopen()?;
+                continue;
+            }
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(seconds) = line.strip_prefix("sleep ") {
+                match seconds.trim().parse().map(Duration::try_from_secs_f64) {
+                    Ok(Ok(duration)) => std::thread::sleep(duration),
+                    _ => warn!("Invalid sleep duration: {seconds}"),
+                }
+                continue;
+            }
+            if let Some(card_id) = present.take() {
+                tx.send(CardEvent::Removed(card_id)).map_err(Error::Send)?;
+            }
+            if line != "remove" {
+                let card_id: Arc<str> = Arc::from(line);
+                present = Some(card_id.clone());
+                tx.send(CardEvent::Present(card_id)).map_err(Error::Send)?;
+            }
+        }
+    }
+}
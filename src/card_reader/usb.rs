@@ -0,0 +1,251 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use rusb::{Device, DeviceHandle, Hotplug, HotplugBuilder, UsbContext};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, warn};
+
+use super::{read_reconnecting, CardEvent, CardReader, Presence, ReaderDevice, ReportDecoder};
+use crate::error::Error;
+
+static READ_TIMEOUT: Duration = Duration::from_millis(250);
+/// How many reads in a row may fail before the device is opened again.
+const MAX_READ_ERRORS: usize = 5;
+
+/// The vendor and product ID of a USB device.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)
+    }
+}
+
+/// A USB HID card reader identified by its vendor and product ID.
+///
+/// The reader waits for the device if it is missing and opens it again after it was unplugged.
+pub struct UsbReader {
+    devices: Vec<UsbId>,
+    presence: Presence,
+    decoder: Box<dyn ReportDecoder>,
+}
+
+impl UsbReader {
+    /// Creates a reader for the first present device with one of the given IDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `devices` - The IDs of the supported devices, in order of preference.
+    /// * `presence` - Decides when a card counts as placed or removed.
+    /// * `decoder` - Extracts card IDs from the reports of the device.
+    #[must_use]
+    pub fn new(devices: Vec<UsbId>, presence: Presence, decoder: Box<dyn ReportDecoder>) -> Self {
+        Self {
+            devices,
+            presence,
+            decoder,
+        }
+    }
+}
+
+impl CardReader for UsbReader {
+    fn read(&mut self, tx: &Sender<CardEvent>) -> Result<(), Error> {
+        let context = rusb::Context::new()?;
+        let (tx_arrived, rx_arrived) = crossbeam_channel::bounded(1);
+        let registration = if rusb::has_hotplug() {
+            match HotplugBuilder::new()
+                .enumerate(false)
+                .register(&context, Box::new(Arrivals(tx_arrived)))
+            {
+                Ok(registration) => Some(registration),
+                Err(err) => {
+                    warn!("Failed to watch for USB devices, polling instead: {err}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let mut device = UsbDevice {
+            ids: &self.devices,
+            decoder: self.decoder.as_mut(),
+            hotplug: registration.is_some(),
+            context,
+            rx_arrived,
+        };
+        read_reconnecting(&mut device, &mut self.presence, tx)
+    }
+}
+
+/// The first present device with one of the reader's IDs.
+struct UsbDevice<'a> {
+    ids: &'a [UsbId],
+    decoder: &'a mut dyn ReportDecoder,
+    context: rusb::Context,
+    /// Whether plugged in devices are signalled on `rx_arrived`, or have to be polled for.
+    hotplug: bool,
+    rx_arrived: Receiver<()>,
+}
+
+impl ReaderDevice for UsbDevice<'_> {
+    type Handle = DeviceHandle<rusb::Context>;
+
+    fn open(&mut self) -> Result<(Arc<str>, Self::Handle), Error> {
+        let (id, handle) = self
+            .ids
+            .iter()
+            .find_map(|&id| {
+                self.context
+                    .open_device_with_vid_pid(id.vid, id.pid)
+                    .map(|handle| (id, handle))
+            })
+            .ok_or(Error::Rusb(rusb::Error::NoDevice))?;
+        claim(&handle).inspect_err(|err| warn!("Failed to claim card reader {id}: {err}"))?;
+        Ok((id.to_string().into(), handle))
+    }
+
+    fn read_cards(
+        &mut self,
+        handle: Self::Handle,
+        presence: &mut Presence,
+        tx: &Sender<CardEvent>,
+    ) -> Result<(), Error> {
+        read_reports(&handle, presence, self.decoder, tx)
+    }
+
+    fn wait(&mut self, timeout: Duration) {
+        wait_for_device(&self.context, self.hotplug, &self.rx_arrived, timeout);
+    }
+}
+
+/// Signals that a USB device was plugged in.
+struct Arrivals(Sender<()>);
+
+impl Hotplug<rusb::Context> for Arrivals {
+    fn device_arrived(&mut self, _device: Device<rusb::Context>) {
+        // A full channel already holds a pending signal.
+        let _ = self.0.try_send(());
+    }
+
+    fn device_left(&mut self, _device: Device<rusb::Context>) {}
+}
+
+/// Blocks until a device was plugged in or `timeout` has passed.
+fn wait_for_device(
+    context: &rusb::Context,
+    hotplug: bool,
+    rx_arrived: &Receiver<()>,
+    timeout: Duration,
+) {
+    if !hotplug {
+        std::thread::sleep(timeout);
+        return;
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        if let Err(err) = context.handle_events(Some(remaining)) {
+            error!("Failed to handle USB events: {err}");
+            std::thread::sleep(remaining);
+            return;
+        }
+        if rx_arrived.try_recv().is_ok() {
+            debug!("USB device plugged in");
+            return;
+        }
+    }
+}
+
+/// Decodes the `len` bytes of the latest transfer in `buf`.
+///
+/// Bytes left over from an earlier, longer transfer are not passed on, so they can't complete
+/// a card ID.
+fn decode_transfer(decoder: &mut dyn ReportDecoder, buf: &[u8], len: usize) -> Option<String> {
+    decoder.decode(&buf[..len])
+}
+
+/// Takes the reader's interface over from the kernel driver.
+fn claim(handle: &DeviceHandle<rusb::Context>) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    {
+        if handle.kernel_driver_active(0)? {
+            handle.detach_kernel_driver(0)?;
+        }
+    }
+
+    handle.claim_interface(0)?;
+    Ok(())
+}
+
+/// Reads cards from a claimed device until it fails.
+fn read_reports(
+    handle: &DeviceHandle<rusb::Context>,
+    presence: &mut Presence,
+    decoder: &mut dyn ReportDecoder,
+    tx: &Sender<CardEvent>,
+) -> Result<(), Error> {
+    let mut buf = [0; 128];
+    let mut errors = 0;
+    debug!("Card reader ready");
+    loop {
+        match handle.read_interrupt(0x81, &mut buf, READ_TIMEOUT) {
+            Ok(len) => {
+                errors = 0;
+                if let Some(card_id) = decode_transfer(decoder, &buf, len) {
+                    presence.seen(Arc::from(card_id), tx)?;
+                }
+            }
+            Err(rusb::Error::Timeout) => errors = 0,
+            Err(rusb::Error::NoDevice) => return Err(Error::Rusb(rusb::Error::NoDevice)),
+            Err(e) => {
+                error!("Error: {e}");
+                errors += 1;
+                if errors >= MAX_READ_ERRORS {
+                    return Err(Error::Rusb(e));
+                }
+            }
+        }
+        presence.check(tx)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card_reader::{RawUid, SparseReport};
+
+    #[test]
+    fn short_transfers_ignore_stale_bytes() {
+        let mut buf = [0; 128];
+        for i in (2..128).step_by(16) {
+            buf[i] = 0x1e;
+        }
+        assert_eq!(
+            decode_transfer(&mut SparseReport, &buf, 128).as_deref(),
+            Some("1E1E1E1E1E1E1E1E")
+        );
+        // A 64-byte transfer overwrites only the first half of the buffer.
+        assert_eq!(decode_transfer(&mut SparseReport, &buf, 64), None);
+    }
+
+    #[test]
+    fn short_transfers_decode_their_own_bytes() {
+        let mut buf = [0xff; 128];
+        buf[..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef, 0, 0, 0, 0]);
+        assert_eq!(
+            decode_transfer(&mut RawUid, &buf, 8).as_deref(),
+            Some("DEADBEEF")
+        );
+    }
+}
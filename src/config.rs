@@ -0,0 +1,524 @@
+use std::{fs::File, net::SocketAddr, path::Path, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    card_reader::{DecoderKind, UsbId},
+    error::Error,
+    parental::ParentalConfig,
+};
+
+static DEFAULT_CONFIG_FILE: &str = "config.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub library: Arc<str>,
+    pub reader: ReaderConfig,
+    pub hotspot: HotspotConfig,
+    pub sounds: SoundsConfig,
+    pub manager: ManagerConfig,
+    pub pairing: PairingConfig,
+    pub admin: AdminConfig,
+    pub playback: PlaybackConfig,
+    pub sleep: SleepConfig,
+    pub fade: FadeConfig,
+    pub volume: VolumeConfig,
+    pub parental: ParentalConfig,
+    /// The file the configuration was loaded from, or would be saved to.
+    #[serde(skip)]
+    pub path: Arc<str>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReaderConfig {
+    pub vid: u16,
+    pub pid: u16,
+    /// Further readers to look for if the one above is missing.
+    pub probe: Vec<UsbId>,
+    /// How the reports of the reader hold the card ID.
+    pub decoder: DecoderKind,
+    /// Reads card IDs from a script file instead of the USB reader, `-` for stdin.
+    pub simulate: Option<Arc<str>>,
+    /// Reads card IDs typed by a keyboard reader from this input device instead of the USB
+    /// reader, e.g. `/dev/input/event0`.
+    pub evdev: Option<Arc<str>>,
+    /// How long a card may go unreported before it counts as lifted from the reader.
+    pub removal_timeout_ms: u64,
+    /// How long after a card was placed placing it again is ignored, for each card.
+    pub debounce_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotspotConfig {
+    pub interface: Arc<str>,
+    pub ssid: Arc<str>,
+    pub password: Arc<str>,
+}
+
+/// Voice and sound prompts about the state of the box.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoundsConfig {
+    /// The sound pack, a folder with a `<prompt>.wav` for each prompt, e.g. `card_unknown.wav`.
+    pub dir: Arc<str>,
+    /// Speaks the prompts missing from the sound pack.
+    pub tts: Option<TtsConfig>,
+    /// How much the music is lowered while a prompt plays, `0` to play prompts over it.
+    pub duck_db: f32,
+}
+
+/// An offline text-to-speech engine that takes `-w <file> <text>` like `espeak-ng`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TtsConfig {
+    pub command: Arc<str>,
+    /// Arguments passed before the output file and text, e.g. `["-v", "de"]`.
+    pub args: Vec<Arc<str>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ManagerConfig {
+    pub listen: Arc<str>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PairingConfig {
+    /// How long pairing waits for a new card before it gives up.
+    pub timeout_seconds: u64,
+    /// How often a new card has to be scanned before it is added.
+    pub confirmations: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// How long the admin mode entered with an admin card waits for the next scan.
+    pub window_seconds: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaybackConfig {
+    pub end_of_queue: EndOfQueue,
+    /// Pauses when the playing card is lifted and resumes when it is placed back, for
+    /// readers that report a card for as long as it lies on them.
+    pub hold_mode: bool,
+    /// What placing the card that is already playing does.
+    pub rescan: RescanPolicy,
+}
+
+/// What happens when the card of the current queue is scanned again while it plays.
+///
+/// A paused card always resumes, and the `Shuffle` card always picks another track.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum RescanPolicy {
+    #[default]
+    Ignore,
+    TogglePause,
+    /// Starts the card from the beginning, or from its bookmark for audiobooks.
+    Restart,
+    Next,
+}
+
+/// Volume levels in decibels relative to full scale.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct VolumeConfig {
+    pub min_db: f32,
+    /// The loudest level reachable with the volume cards.
+    pub max_db: f32,
+    pub step_db: f32,
+    /// The level used until the volume is changed for the first time.
+    pub default_db: f32,
+    pub backend: VolumeBackend,
+}
+
+/// Where the volume is applied.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub enum VolumeBackend {
+    /// Scales the samples before they are played.
+    #[default]
+    Software,
+    /// Sets a hardware mixer control, e.g. `{ "device": "default", "control": "Master" }`.
+    Alsa { device: Arc<str>, control: Arc<str> },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SleepConfig {
+    /// Lets the current track end before pausing instead of fading out.
+    pub finish_track: bool,
+    pub fade_seconds: u64,
+}
+
+/// Fade durations in milliseconds, `0` to switch abruptly.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FadeConfig {
+    /// Fades in a new queue and the playback on resume.
+    pub fade_in_ms: u64,
+    /// Fades out the previous queue and the playback on pause.
+    pub fade_out_ms: u64,
+    /// Starts a new queue while the previous one fades out instead of after it.
+    pub crossfade: bool,
+    /// Overlaps consecutive tracks of a queue, fading one into the next.
+    ///
+    /// Tracks of different channels or sample rates fade out and in without overlapping.
+    pub track_crossfade_ms: u64,
+}
+
+/// What happens once the last track of a card has finished.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub enum EndOfQueue {
+    #[default]
+    Stop,
+    Repeat,
+    /// Plays the given sound once.
+    Chime(Arc<str>),
+    /// Repeats the queue until the given number of minutes has passed.
+    Sleep {
+        minutes: u64,
+    },
+}
+
+impl Config {
+    /// Loads the configuration from the file and applies environment and command line overrides.
+    ///
+    /// The file is taken from `--config <path>` or `MARLINBOX_CONFIG` and defaults to
+    /// `config.json`, which may be absent. Every setting can be overridden with an environment
+    /// variable (e.g. `MARLINBOX_LISTEN`) or a command line flag (e.g. `--listen`), in that order.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The command line arguments without the program name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if the file can't be read or parsed, an argument is unknown or a
+    /// setting is invalid.
+    pub fn load<I: IntoIterator<Item = String>>(args: I) -> Result<Self, Error> {
+        let args = parse_args(args)?;
+
+        let explicit_path = args
+            .iter()
+            .find(|(flag, _)| flag == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var("MARLINBOX_CONFIG").ok());
+
+        let mut config = match &explicit_path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            None => {
+                debug!("No {DEFAULT_CONFIG_FILE} found, using defaults");
+                Self::default()
+            }
+        };
+
+        for flag in FLAGS {
+            let env = format!("MARLINBOX_{}", flag.to_uppercase());
+            if let Ok(value) = std::env::var(&env) {
+                config.set(flag, &value)?;
+            }
+        }
+        for (flag, value) in &args {
+            if flag != "config" {
+                config.set(flag, value)?;
+            }
+        }
+
+        config.path = explicit_path.map_or_else(|| DEFAULT_CONFIG_FILE.into(), Arc::from);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads the configuration from a file without applying overrides.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the configuration file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if there was an error reading the configuration from the file.
+    pub fn from_file<P: AsRef<str>>(file_path: P) -> Result<Self, Error> {
+        let file = File::open(file_path.as_ref()).map_err(Error::File)?;
+        serde_json::from_reader(file).map_err(Error::Deserialize)
+    }
+
+    fn set(&mut self, flag: &str, value: &str) -> Result<(), Error> {
+        match flag {
+            "library" => self.library = value.into(),
+            "vid" => self.reader.vid = parse_id(flag, value)?,
+            "pid" => self.reader.pid = parse_id(flag, value)?,
+            "simulate" => self.reader.simulate = Some(value.into()),
+            "evdev" => self.reader.evdev = Some(value.into()),
+            "interface" => self.hotspot.interface = value.into(),
+            "ssid" => self.hotspot.ssid = value.into(),
+            "password" => self.hotspot.password = value.into(),
+            "listen" => self.manager.listen = value.into(),
+            _ => return Err(Error::Config(format!("Unknown option --{flag}"))),
+        }
+        Ok(())
+    }
+
+    /// Checks the configuration for invalid values.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` describing the first invalid setting.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.library.is_empty() {
+            return Err(Error::Config("library must not be empty".into()));
+        }
+        if self.hotspot.interface.is_empty() {
+            return Err(Error::Config("hotspot.interface must not be empty".into()));
+        }
+        if self.hotspot.ssid.is_empty() || self.hotspot.ssid.len() > 32 {
+            return Err(Error::Config(
+                "hotspot.ssid must be between 1 and 32 bytes long".into(),
+            ));
+        }
+        if !(8..=63).contains(&self.hotspot.password.len()) {
+            return Err(Error::Config(
+                "hotspot.password must be between 8 and 63 characters long".into(),
+            ));
+        }
+        if let Err(err) = self.manager.listen.parse::<SocketAddr>() {
+            return Err(Error::Config(format!(
+                "manager.listen is not a valid address '{}': {err}",
+                self.manager.listen
+            )));
+        }
+        if self.pairing.confirmations == 0 {
+            return Err(Error::Config(
+                "pairing.confirmations must be positive".into(),
+            ));
+        }
+        if let EndOfQueue::Sleep { minutes: 0 } = self.playback.end_of_queue {
+            return Err(Error::Config(
+                "playback.end_of_queue sleep minutes must be positive".into(),
+            ));
+        }
+        let volume = &self.volume;
+        if volume.max_db > 0.0 {
+            return Err(Error::Config("volume.max_db must not be above 0 dB".into()));
+        }
+        if volume.min_db >= volume.max_db {
+            return Err(Error::Config(
+                "volume.min_db must be below volume.max_db".into(),
+            ));
+        }
+        if volume.step_db <= 0.0 {
+            return Err(Error::Config("volume.step_db must be positive".into()));
+        }
+        if !(volume.min_db..=volume.max_db).contains(&volume.default_db) {
+            return Err(Error::Config(
+                "volume.default_db must be between volume.min_db and volume.max_db".into(),
+            ));
+        }
+        if let VolumeBackend::Alsa { device, control } = &volume.backend {
+            if device.is_empty()
+                || device.contains('\0')
+                || control.is_empty()
+                || control.contains('\0')
+            {
+                return Err(Error::Config(
+                    "volume.backend ALSA device and control must be non-empty names".into(),
+                ));
+            }
+        }
+        self.parental.validate()?;
+        if self.sounds.duck_db > 0.0 {
+            return Err(Error::Config(
+                "sounds.duck_db must not be above 0 dB".into(),
+            ));
+        }
+        if self.sounds.tts.is_none() && !Path::new(self.sounds.dir.as_ref()).is_dir() {
+            warn!("Sound pack not found: {}", self.sounds.dir);
+        }
+        Ok(())
+    }
+}
+
+/// Command line flags that take a value, also available as `MARLINBOX_<FLAG>` variables.
+static FLAGS: [&str; 9] = [
+    "library",
+    "vid",
+    "pid",
+    "simulate",
+    "evdev",
+    "interface",
+    "ssid",
+    "password",
+    "listen",
+];
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Vec<(String, String)>, Error> {
+    let mut parsed = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(Error::Config(format!("Unexpected argument '{arg}'")));
+        };
+        if flag != "config" && !FLAGS.contains(&flag) {
+            return Err(Error::Config(format!("Unknown option --{flag}")));
+        }
+        let Some(value) = args.next() else {
+            return Err(Error::Config(format!("--{flag} requires a value")));
+        };
+        parsed.push((flag.to_string(), value));
+    }
+    Ok(parsed)
+}
+
+fn parse_id(flag: &str, value: &str) -> Result<u16, Error> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|err| Error::Config(format!("Invalid {flag} '{value}': {err}")))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            library: "music.json".into(),
+            reader: ReaderConfig::default(),
+            hotspot: HotspotConfig::default(),
+            sounds: SoundsConfig::default(),
+            manager: ManagerConfig::default(),
+            pairing: PairingConfig::default(),
+            admin: AdminConfig::default(),
+            playback: PlaybackConfig::default(),
+            sleep: SleepConfig::default(),
+            fade: FadeConfig::default(),
+            volume: VolumeConfig::default(),
+            parental: ParentalConfig::default(),
+            path: DEFAULT_CONFIG_FILE.into(),
+        }
+    }
+}
+
+impl ReaderConfig {
+    /// Returns the IDs of the supported USB readers, in order of preference.
+    #[must_use]
+    pub fn usb_devices(&self) -> Vec<UsbId> {
+        let mut devices = vec![UsbId {
+            vid: self.vid,
+            pid: self.pid,
+        }];
+        for id in &self.probe {
+            if !devices.contains(id) {
+                devices.push(*id);
+            }
+        }
+        devices
+    }
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        Self {
+            vid: 0xffff,
+            pid: 0x0035,
+            probe: vec![],
+            decoder: DecoderKind::default(),
+            simulate: None,
+            evdev: None,
+            removal_timeout_ms: 2000,
+            debounce_ms: 1000,
+        }
+    }
+}
+
+impl Default for HotspotConfig {
+    fn default() -> Self {
+        Self {
+            interface: "wlp59s0".into(),
+            ssid: "MARLIN".into(),
+            password: "M4rl!nB0x".into(),
+        }
+    }
+}
+
+impl Default for SoundsConfig {
+    fn default() -> Self {
+        Self {
+            dir: "sounds".into(),
+            tts: None,
+            duck_db: -12.0,
+        }
+    }
+}
+
+impl Default for TtsConfig {
+    fn default() -> Self {
+        Self {
+            command: "espeak-ng".into(),
+            args: vec![],
+        }
+    }
+}
+
+impl Default for SleepConfig {
+    fn default() -> Self {
+        Self {
+            finish_track: false,
+            fade_seconds: 10,
+        }
+    }
+}
+
+impl Default for FadeConfig {
+    fn default() -> Self {
+        Self {
+            fade_in_ms: 500,
+            fade_out_ms: 500,
+            crossfade: false,
+            track_crossfade_ms: 0,
+        }
+    }
+}
+
+impl Default for VolumeConfig {
+    fn default() -> Self {
+        Self {
+            min_db: -40.0,
+            max_db: 0.0,
+            step_db: 3.0,
+            default_db: -12.0,
+            backend: VolumeBackend::default(),
+        }
+    }
+}
+
+impl Default for PairingConfig {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: 60,
+            confirmations: 3,
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self { window_seconds: 20 }
+    }
+}
+
+impl Default for ManagerConfig {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:8080".into(),
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use std::{fmt, sync::Arc};
+use std::fmt;
+
+use crate::card_reader::CardEvent;
 
 pub enum Error {
     Io(std::io::Error),
@@ -9,10 +11,12 @@ pub enum Error {
     Recv(crossbeam_channel::RecvError),
     File(std::io::Error),
     Rusb(rusb::Error),
-    Send(crossbeam_channel::SendError<Arc<str>>),
+    Send(crossbeam_channel::SendError<CardEvent>),
     MutexPoison,
     Serialize(serde_json::Error),
     Deserialize(serde_json::Error),
+    Config(String),
+    Alsa(alsa::Error),
 }
 
 impl From<serde_json::Error> for Error {
@@ -36,6 +40,8 @@ impl fmt::Debug for Error {
             Self::MutexPoison => write!(f, "Mutex poison error"),
             Self::Serialize(err) => write!(f, "Serialize error: {err}"),
             Self::Deserialize(err) => write!(f, "Deserialize error: {err}"),
+            Self::Config(err) => write!(f, "Config error: {err}"),
+            Self::Alsa(err) => write!(f, "ALSA error: {err}"),
         }
     }
 }
@@ -55,6 +61,8 @@ impl fmt::Display for Error {
             Self::MutexPoison => write!(f, "Mutex poison error"),
             Self::Serialize(err) => write!(f, "Serialize error: {err}"),
             Self::Deserialize(err) => write!(f, "Deserialize error: {err}"),
+            Self::Config(err) => write!(f, "Config error: {err}"),
+            Self::Alsa(err) => write!(f, "ALSA error: {err}"),
         }
     }
 }
@@ -65,6 +73,12 @@ impl From<rusb::Error> for Error {
     }
 }
 
+impl From<alsa::Error> for Error {
+    fn from(err: alsa::Error) -> Self {
+        Self::Alsa(err)
+    }
+}
+
 impl From<rodio::StreamError> for Error {
     fn from(err: rodio::StreamError) -> Self {
         Self::Stream(err)
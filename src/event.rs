@@ -0,0 +1,31 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Serialize;
+
+use crate::{admin::AdminAction, card_reader::ReaderStatus};
+
+/// Something that happened on the box, published to the manager's event stream.
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    CardScanned { id: Arc<str>, known: bool },
+    CardRemoved { id: Arc<str> },
+    ReaderChanged { status: ReaderStatus },
+    CardBlocked { id: Arc<str> },
+    TrackStarted { track: Arc<str> },
+    TrackFinished { track: Arc<str> },
+    QueueFinished,
+    PairingToggled { enabled: bool },
+    PairingProgress { id: Arc<str>, left: usize },
+    PairingTimedOut,
+    CardPaired { id: Arc<str> },
+    CardSelected { id: Arc<str> },
+    CardDeleted { id: Arc<str> },
+    AdminToggled { enabled: bool },
+    AdminActionSelected { action: AdminAction },
+    VolumeChanged { volume_db: f32 },
+    SleepTimerSet { after: Duration },
+    SleepTimerCancelled,
+    SleepTimerElapsed,
+    Error { message: String },
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rodio::{Sample, Source};
+
+static FADER_UPDATE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A volume factor that ramps over time, shared between the player and the sources it plays.
+#[derive(Clone)]
+pub struct Fader(Arc<Mutex<Ramp>>);
+
+struct Ramp {
+    from: f32,
+    to: f32,
+    started: Instant,
+    duration: Duration,
+    generation: u64,
+}
+
+impl Ramp {
+    fn factor(&self) -> f32 {
+        if self.duration.is_zero() {
+            return self.to;
+        }
+        let progress =
+            (self.started.elapsed().as_secs_f32() / self.duration.as_secs_f32()).min(1.0);
+        (self.to - self.from).mul_add(progress, self.from)
+    }
+}
+
+impl Fader {
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Ramp {
+            from: 1.0,
+            to: 1.0,
+            started: Instant::now(),
+            duration: Duration::ZERO,
+            generation: 0,
+        })))
+    }
+
+    /// Returns the current factor, or full volume if the fader is poisoned.
+    #[must_use]
+    pub fn factor(&self) -> f32 {
+        self.0.lock().map_or(1.0, |ramp| ramp.factor())
+    }
+
+    /// Ramps from the current factor to `to` over `duration`.
+    ///
+    /// Returns an ID of this fade for [`Fader::is_current`].
+    pub fn fade_to(&self, to: f32, duration: Duration) -> u64 {
+        let Ok(mut ramp) = self.0.lock() else {
+            return 0;
+        };
+        ramp.from = ramp.factor();
+        ramp.to = to;
+        ramp.started = Instant::now();
+        ramp.duration = duration;
+        ramp.generation += 1;
+        ramp.generation
+    }
+
+    pub fn set(&self, factor: f32) {
+        self.fade_to(factor, Duration::ZERO);
+    }
+
+    /// Returns whether the fade with the given ID hasn't been replaced by another one.
+    #[must_use]
+    pub fn is_current(&self, generation: u64) -> bool {
+        self.0
+            .lock()
+            .is_ok_and(|ramp| ramp.generation == generation)
+    }
+
+    /// Applies the fader to `source`.
+    pub fn apply<S>(&self, source: S) -> impl Source<Item = S::Item> + Send
+    where
+        S: Source + Send,
+        S::Item: Sample + Send,
+    {
+        let fader = self.clone();
+        source
+            .amplify(1.0)
+            .periodic_access(FADER_UPDATE_INTERVAL, move |source| {
+                source.set_factor(fader.factor());
+            })
+    }
+}
+
+impl Default for Fader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A track that is read by the end of the previous track and then played from where it left
+/// off.
+pub struct Shared<S>(Arc<Mutex<S>>);
+
+impl<S> Shared<S> {
+    pub fn new(input: S) -> Self {
+        Self(Arc::new(Mutex::new(input)))
+    }
+}
+
+impl<S> Clone for Shared<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<S> Iterator for Shared<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        self.0.lock().ok()?.next()
+    }
+}
+
+impl<S> Source for Shared<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.0.lock().ok()?.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.0.lock().map_or(1, |input| input.channels())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.0.lock().map_or(44_100, |input| input.sample_rate())
+    }
+
+    /// Unknown, as the previous track may have played the beginning.
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Fades out the last `duration` of a track while fading in the beginning of the next one.
+///
+/// The end of the track is found by reading `duration` ahead, so tracks of unknown length
+/// overlap as well. The next track must have the same channels and sample rate; without one
+/// the track only fades out.
+pub struct Crossfade<S: Iterator, N> {
+    input: S,
+    next: Option<N>,
+    channels: usize,
+    fade_samples: usize,
+    buffer: VecDeque<S::Item>,
+    read: usize,
+    /// The number of samples overlapping the next track, once the track has been read.
+    tail: Option<usize>,
+}
+
+impl<S, N> Crossfade<S, N>
+where
+    S: Source,
+    S::Item: Sample,
+    N: Source<Item = S::Item>,
+{
+    pub fn new(input: S, next: Option<N>, duration: Duration) -> Self {
+        let channels = usize::from(input.channels());
+        let frames = duration.as_nanos() * u128::from(input.sample_rate()) / 1_000_000_000;
+        let fade_samples = usize::try_from(frames)
+            .unwrap_or(usize::MAX / channels)
+            .saturating_mul(channels);
+        Self {
+            input,
+            next,
+            channels,
+            fade_samples,
+            buffer: VecDeque::new(),
+            read: 0,
+            tail: None,
+        }
+    }
+
+    /// Reads ahead so the buffer holds the fade and a frame to spare, two frames at a time to
+    /// spread the decoding of the first fill over the playback.
+    fn read_ahead(&mut self) {
+        let frames = if self.buffer.len() < self.fade_samples {
+            2
+        } else {
+            1
+        };
+        for _ in 0..frames * self.channels {
+            if self.buffer.len() > self.fade_samples + self.channels {
+                return;
+            }
+            if let Some(sample) = self.input.next() {
+                self.buffer.push_back(sample);
+                self.read += 1;
+            } else {
+                // The tail starts on a frame, so the tracks' channels line up.
+                let available = self.buffer.len().min(self.fade_samples);
+                let misaligned = (self.read - available) % self.channels;
+                self.tail = Some(available.saturating_sub(misaligned));
+                return;
+            }
+        }
+    }
+}
+
+impl<S, N> Iterator for Crossfade<S, N>
+where
+    S: Source,
+    S::Item: Sample,
+    N: Source<Item = S::Item>,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        if self.tail.is_none() {
+            self.read_ahead();
+        }
+        let sample = self.buffer.pop_front()?;
+        let Some(tail) = self.tail else {
+            return Some(sample);
+        };
+        let remaining = self.buffer.len();
+        if remaining >= tail {
+            return Some(sample);
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let factor = (remaining + 1) as f32 / (tail + 1) as f32;
+        let sample = sample.amplify(factor);
+        match self.next.as_mut().and_then(Iterator::next) {
+            Some(next) => Some(sample.saturating_add(next.amplify(1.0 - factor))),
+            None => Some(sample),
+        }
+    }
+}
+
+impl<S, N> Source for Crossfade<S, N>
+where
+    S: Source,
+    S::Item: Sample,
+    N: Source<Item = S::Item>,
+{
+    /// Unknown, as the track is read ahead of the playback.
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    fn track(channels: u16, samples: Vec<i16>) -> SamplesBuffer<i16> {
+        SamplesBuffer::new(channels, 10, samples)
+    }
+
+    #[test]
+    fn crossfade_overlaps_the_tracks() {
+        let next = Shared::new(track(1, vec![100; 10]));
+        let first = Crossfade::new(
+            track(1, vec![100; 10]),
+            Some(next.clone()),
+            Duration::from_millis(400),
+        );
+        let played: Vec<i16> = first.chain(next).collect();
+        assert_eq!(played.len(), 16);
+        assert!(played.iter().all(|&sample| (99..=100).contains(&sample)));
+    }
+
+    #[test]
+    fn crossfade_fades_out_the_last_track() {
+        let last: Crossfade<_, Shared<SamplesBuffer<i16>>> =
+            Crossfade::new(track(1, vec![100; 10]), None, Duration::from_millis(400));
+        let played: Vec<i16> = last.collect();
+        assert_eq!(&played[..6], &[100; 6]);
+        assert!(played[6..].windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn crossfade_keeps_the_channels_in_line() {
+        let next = Shared::new(track(2, vec![100, -100, 100, -100]));
+        let first = Crossfade::new(
+            track(2, vec![0; 6]),
+            Some(next.clone()),
+            Duration::from_millis(200),
+        );
+        let played: Vec<i16> = first.chain(next).collect();
+        assert_eq!(&played[..2], &[0, 0]);
+        assert!(played[2..]
+            .chunks(2)
+            .all(|frame| frame[0] > 0 && frame[1] < 0));
+    }
+
+    #[test]
+    fn crossfade_overlaps_short_tracks() {
+        let next = Shared::new(track(1, vec![100; 10]));
+        let first = Crossfade::new(
+            track(1, vec![100; 2]),
+            Some(next.clone()),
+            Duration::from_secs(1),
+        );
+        let played: Vec<i16> = first.chain(next).collect();
+        assert!(played.len() < 12);
+    }
+}
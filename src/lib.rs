@@ -1,7 +1,17 @@
+pub mod admin;
 pub mod card;
 pub mod card_reader;
+pub mod config;
 pub mod error;
+pub mod event;
+pub mod fade;
 mod library;
 pub mod manager;
+pub mod mixer;
+pub mod pairing;
+pub mod parental;
+pub mod player;
+pub mod prompt;
 pub mod service;
+pub mod volume;
 pub use library::Library;
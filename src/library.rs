@@ -3,12 +3,16 @@ use std::{collections::HashMap, io::Write, sync::Arc};
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 
-use crate::{card::Card, error::Error};
+use crate::{card::Card, error::Error, player::Bookmark};
 use std::fs::File;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Library {
     music: HashMap<Arc<str>, Option<Card>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    bookmarks: HashMap<Arc<str>, Bookmark>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    volume_db: Option<f32>,
 }
 
 impl Library {
@@ -16,6 +20,8 @@ impl Library {
     pub fn new() -> Self {
         Self {
             music: HashMap::new(),
+            bookmarks: HashMap::new(),
+            volume_db: None,
         }
     }
 
@@ -54,12 +60,88 @@ impl Library {
     }
 
     pub fn remove(&mut self, card_id: &str) -> Option<Card> {
+        self.bookmarks.remove(card_id);
         self.music.remove_entry(card_id).and_then(|(_, v)| v)
     }
 
     pub fn update(&mut self, card_id: &str, music_file: Option<Card>) {
-        self.music
-            .insert(card_id.into(), music_file.map(Into::into));
+        self.music.insert(card_id.into(), music_file);
+    }
+
+    /// Binds `card_id` to `music_file` and saves the library, undoing the change if saving fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `card_id` - The ID of the card, which is added if it is new.
+    /// * `music_file` - What the card plays, if anything.
+    /// * `file_path` - The path to the file where the library will be saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if there was an error writing the library to the file.
+    pub fn update_and_save<P: AsRef<str>>(
+        &mut self,
+        card_id: &str,
+        music_file: Option<Card>,
+        file_path: P,
+    ) -> Result<(), Error> {
+        self.change_and_save(card_id, Some(music_file), file_path)
+    }
+
+    /// Removes `card_id` and saves the library, undoing the removal if saving fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `card_id` - The ID of the card.
+    /// * `file_path` - The path to the file where the library will be saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if there was an error writing the library to the file.
+    pub fn remove_and_save<P: AsRef<str>>(
+        &mut self,
+        card_id: &str,
+        file_path: P,
+    ) -> Result<(), Error> {
+        self.change_and_save(card_id, None, file_path)
+    }
+
+    /// Replaces the entry of `card_id`, or removes it for `None`, and saves the library.
+    fn change_and_save<P: AsRef<str>>(
+        &mut self,
+        card_id: &str,
+        entry: Option<Option<Card>>,
+        file_path: P,
+    ) -> Result<(), Error> {
+        let previous = self.music.get(card_id).cloned();
+        let bookmark = self.bookmark(card_id);
+        match entry {
+            Some(music_file) => self.update(card_id, music_file),
+            None => {
+                self.remove(card_id);
+            }
+        }
+        let result = self.save_to_file(file_path);
+        if result.is_err() {
+            match previous {
+                Some(music_file) => self.update(card_id, music_file),
+                None => {
+                    self.music.remove(card_id);
+                }
+            }
+            self.set_bookmark(card_id, bookmark);
+        }
+        result
+    }
+
+    #[must_use]
+    pub fn cards(&self) -> &HashMap<Arc<str>, Option<Card>> {
+        &self.music
+    }
+
+    #[must_use]
+    pub fn contains(&self, card_id: &str) -> bool {
+        self.music.contains_key(card_id)
     }
 
     #[must_use]
@@ -68,8 +150,36 @@ impl Library {
     }
 
     #[must_use]
-    pub fn get_random(&self) -> Option<&Card> {
-        let play_cards: Vec<&Card> = self.music.values().flatten().collect();
+    pub fn bookmark(&self, card_id: &str) -> Option<Bookmark> {
+        self.bookmarks.get(card_id).copied()
+    }
+
+    pub fn set_bookmark(&mut self, card_id: &str, bookmark: Option<Bookmark>) {
+        match bookmark {
+            Some(bookmark) => self.bookmarks.insert(card_id.into(), bookmark),
+            None => self.bookmarks.remove(card_id),
+        };
+    }
+
+    /// Returns the volume that was last set, in decibels.
+    #[must_use]
+    pub const fn volume_db(&self) -> Option<f32> {
+        self.volume_db
+    }
+
+    pub fn set_volume_db(&mut self, volume_db: f32) {
+        self.volume_db = Some(volume_db);
+    }
+
+    /// Returns the card of a random ID for which `allowed` returns `true`.
+    #[must_use]
+    pub fn get_random(&self, allowed: impl Fn(&str) -> bool) -> Option<&Card> {
+        let play_cards: Vec<&Card> = self
+            .music
+            .iter()
+            .filter(|(card_id, _)| allowed(card_id))
+            .filter_map(|(_, card)| card.as_ref())
+            .collect();
 
         play_cards.choose(&mut rand::thread_rng()).copied()
     }
@@ -80,3 +190,63 @@ impl Default for Library {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const UNWRITABLE: &str = "/nonexistent/music.json";
+
+    fn library() -> Library {
+        let mut library = Library::new();
+        library.update("book", Some(Card::Audiobook("book.mp3".into())));
+        library.set_bookmark(
+            "book",
+            Some(Bookmark {
+                track: 1,
+                position: Duration::from_secs(5),
+            }),
+        );
+        library
+    }
+
+    #[test]
+    fn failed_update_restores_the_previous_card() {
+        let mut library = library();
+        assert!(library
+            .update_and_save("book", Some(Card::Pause), UNWRITABLE)
+            .is_err());
+        assert!(
+            matches!(library.get("book"), Some(Card::Audiobook(file)) if file.as_ref() == "book.mp3")
+        );
+        assert!(library.update_and_save("new", None, UNWRITABLE).is_err());
+        assert!(!library.contains("new"));
+    }
+
+    #[test]
+    fn failed_removal_restores_the_card_and_bookmark() {
+        let mut library = library();
+        let bookmark = library.bookmark("book");
+        assert!(library.remove_and_save("book", UNWRITABLE).is_err());
+        assert!(
+            matches!(library.get("book"), Some(Card::Audiobook(file)) if file.as_ref() == "book.mp3")
+        );
+        assert_eq!(library.bookmark("book"), bookmark);
+    }
+
+    #[test]
+    fn get_random_only_picks_allowed_cards() {
+        let mut library = library();
+        library.update("song", Some(Card::Play("song.mp3".into())));
+        library.add("unbound");
+        for _ in 0..20 {
+            assert!(matches!(
+                library.get_random(|id| id == "song"),
+                Some(Card::Play(file)) if file.as_ref() == "song.mp3"
+            ));
+        }
+        assert!(library.get_random(|id| id == "unbound").is_none());
+    }
+}
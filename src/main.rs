@@ -1,26 +1,62 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use marlinbox_rs::{card_reader, service, Library};
-
-const VID: u16 = 0xffff;
-const PID: u16 = 0x0035;
+use marlinbox_rs::{
+    card_reader::{EvdevReader, Presence, SimulatedReader, UsbReader},
+    config::Config,
+    service, Library,
+};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
-    let music: Arc<Mutex<Library>> = Arc::new(Mutex::new(Library::from_file("music.json")?));
+    let config = Config::load(std::env::args().skip(1))?;
+    let music: Arc<Mutex<Library>> = Arc::new(Mutex::new(Library::from_file(&config.library)?));
 
-    let (tx_card, rx_card) = crossbeam_channel::bounded(10);
     let (tx_manager_shutdown, rx_manager_shutdown) = crossbeam_channel::bounded(1);
+    let presence = Presence::new(
+        Duration::from_millis(config.reader.removal_timeout_ms),
+        Duration::from_millis(config.reader.debounce_ms),
+    );
 
-    let mut handles = vec![];
-    let reader_handle = std::thread::spawn(move || card_reader::read(VID, PID, &tx_card));
-    handles.push(reader_handle);
-    service::run(&music, tx_manager_shutdown, rx_manager_shutdown, &rx_card)?;
-
-    for handle in handles {
-        let _ = handle.join().unwrap();
+    match config.reader.simulate.as_deref() {
+        Some("-") => service::run(
+            &config,
+            &music,
+            tx_manager_shutdown,
+            rx_manager_shutdown,
+            SimulatedReader::stdin(),
+        )?,
+        Some(script) => service::run(
+            &config,
+            &music,
+            tx_manager_shutdown,
+            rx_manager_shutdown,
+            SimulatedReader::from_file(script)?,
+        )?,
+        None => match config.reader.evdev.as_deref() {
+            Some(device) => service::run(
+                &config,
+                &music,
+                tx_manager_shutdown,
+                rx_manager_shutdown,
+                EvdevReader::open(device, presence),
+            )?,
+            None => service::run(
+                &config,
+                &music,
+                tx_manager_shutdown,
+                rx_manager_shutdown,
+                UsbReader::new(
+                    config.reader.usb_devices(),
+                    presence,
+                    config.reader.decoder.build(),
+                ),
+            )?,
+        },
     }
     Ok(())
 }
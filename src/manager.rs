@@ -1,21 +1,64 @@
-use std::{fs, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
 
 use axum::{
-    extract::{Multipart, State},
-    response::IntoResponse,
+    extract::{Multipart, Path, State},
+    http::StatusCode,
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse,
+    },
     routing::{get, post},
+    Extension, Json,
 };
 use crossbeam_channel::{Receiver, Sender};
-use tokio::runtime::Runtime;
+use serde::Deserialize;
+use tokio::{
+    runtime::Runtime,
+    sync::{broadcast, watch},
+};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, WatchStream},
+    Stream, StreamExt,
+};
 use tower_http::services::{ServeDir, ServeFile};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    card::Card,
+    event::Event,
+    library::Library,
+    pairing::PairingRequest,
+    parental::ParentalConfig,
+    player::is_audio_file,
+    prompt::Prompt,
+    service::{Command, Status},
+};
+
+/// The state shared between the Manager's handlers.
+#[derive(Clone)]
+pub struct AppState {
+    pub tx_pairing: Arc<Sender<PairingRequest>>,
+    pub tx_command: Arc<Sender<Command>>,
+    pub events: broadcast::Sender<Event>,
+    pub parental: Arc<Mutex<ParentalConfig>>,
+    pub config_path: Arc<str>,
+    pub library: Arc<Mutex<Library>>,
+    pub library_path: Arc<str>,
+}
 
 /// Starts the Manager and listens for incoming connections.
 ///
 /// # Arguments
 ///
 /// * `connection_string` - A string representing the connection address and port.
-/// * `tx_pairing` - An Arc-wrapped Sender used for message passing.
+/// * `state` - The state shared with the service.
 /// * `rx_shutdown` - A Receiver used to receive shutdown signals.
 ///
 /// # Returns
@@ -23,17 +66,35 @@ use tracing::{debug, error, info};
 /// Returns `Ok(())` if the server started successfully, otherwise returns an error.
 pub fn serve(
     connection_string: &str,
-    tx_pairing: Arc<Sender<()>>,
+    state: AppState,
     rx_shutdown: Arc<Receiver<()>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let rt = Runtime::new()?;
+    let (tx_stopping, rx_stopping) = watch::channel(false);
     let app = axum::Router::new()
         .nest_service("/assets", ServeDir::new(PathBuf::from("assets")))
         .route_service("/", ServeFile::new(PathBuf::from("assets/index.html")))
-        .route("/pair", get(handler))
+        .route(
+            "/pair",
+            get(handler).post(start_pairing).delete(cancel_pairing),
+        )
         .route("/failed", get(|| async { "Failed to send message" }))
         .route("/upload", post(upload_file))
-        .with_state(tx_pairing);
+        .route("/uploads", get(list_uploads))
+        .route("/cards", get(list_cards))
+        .route(
+            "/cards/:id",
+            get(get_card).put(update_card).delete(delete_card),
+        )
+        .route("/cards/:id/play", post(play_card))
+        .route("/control/:action", post(control))
+        .route("/status", get(status))
+        .route("/events", get(events))
+        .route("/parental", get(get_parental).put(update_parental))
+        .route("/sleep", post(set_sleep_timer).delete(cancel_sleep_timer))
+        .route("/battery/low", post(low_battery))
+        .layer(Extension(Stopping(rx_stopping)))
+        .with_state(state);
 
     rt.block_on(async {
         let listener = match tokio::net::TcpListener::bind(connection_string).await {
@@ -48,10 +109,14 @@ pub fn serve(
         };
         match axum::serve(listener, app)
             .with_graceful_shutdown(async move {
-                match rx_shutdown.recv() {
-                    Ok(()) => info!("Manager shutting down"),
-                    Err(err) => error!("Manager failed to shut down: {err}"),
+                // Waiting on the channel blocks, which would stall a runtime with one worker.
+                match tokio::task::spawn_blocking(move || rx_shutdown.recv()).await {
+                    Ok(Ok(())) => info!("Manager shutting down"),
+                    Ok(Err(err)) => error!("Manager failed to shut down: {err}"),
+                    Err(err) => error!("Failed to wait for the manager shutdown: {err}"),
                 }
+                // Open event streams would keep the graceful shutdown waiting forever.
+                tx_stopping.send_replace(true);
             })
             .await
         {
@@ -62,8 +127,8 @@ pub fn serve(
     Ok(())
 }
 
-async fn handler(State(state): State<Arc<Sender<()>>>) -> impl IntoResponse {
-    match state.send(()) {
+async fn handler(State(state): State<AppState>) -> impl IntoResponse {
+    match state.tx_pairing.try_send(PairingRequest::Toggle) {
         Ok(()) => {
             info!("Sent pairing request");
             "Pairing"
@@ -75,6 +140,43 @@ async fn handler(State(state): State<Arc<Sender<()>>>) -> impl IntoResponse {
     }
 }
 
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PairingBody {
+    target: Option<Card>,
+    /// Lets a card that is already bound be paired again.
+    reassign: bool,
+}
+
+/// Starts pairing, optionally assigning the new card to the target in the body.
+async fn start_pairing(
+    State(state): State<AppState>,
+    body: Option<Json<PairingBody>>,
+) -> Result<StatusCode, StatusCode> {
+    let Json(body) = body.unwrap_or_default();
+    send_pairing_request(
+        &state,
+        PairingRequest::Start {
+            target: body.target,
+            reassign: body.reassign,
+        },
+    )?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn cancel_pairing(State(state): State<AppState>) -> Result<StatusCode, StatusCode> {
+    send_pairing_request(&state, PairingRequest::Cancel)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Hands `request` to the service without blocking the async runtime while it is busy.
+fn send_pairing_request(state: &AppState, request: PairingRequest) -> Result<(), StatusCode> {
+    state.tx_pairing.try_send(request).map_err(|err| {
+        error!("Failed to send pairing request: {err}");
+        StatusCode::SERVICE_UNAVAILABLE
+    })
+}
+
 async fn upload_file(mut multipart: Multipart) -> impl IntoResponse {
     while let Ok(resp) = multipart.next_field().await {
         if let Some(field) = resp {
@@ -106,3 +208,229 @@ async fn upload_file(mut multipart: Multipart) -> impl IntoResponse {
     }
     "File uploaded"
 }
+
+/// Lists the uploaded audio files and the folders containing them, which cards can play.
+async fn list_uploads() -> Json<Vec<String>> {
+    let mut uploads = vec![];
+    collect_uploads(std::path::Path::new("uploads"), &mut uploads);
+    uploads.sort();
+    Json(uploads)
+}
+
+fn collect_uploads(dir: &std::path::Path, uploads: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            debug!("Failed to read directory {}: {err}", dir.to_string_lossy());
+            return;
+        }
+    };
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        if path.is_dir() {
+            uploads.push(path.to_string_lossy().into_owned());
+            collect_uploads(&path, uploads);
+        } else if is_audio_file(&path) {
+            uploads.push(path.to_string_lossy().into_owned());
+        }
+    }
+}
+
+fn lock_library(state: &AppState) -> Result<MutexGuard<'_, Library>, StatusCode> {
+    state.library.lock().map_err(|_| {
+        error!("Failed to lock library");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn list_cards(
+    State(state): State<AppState>,
+) -> Result<Json<HashMap<Arc<str>, Option<Card>>>, StatusCode> {
+    let library = lock_library(&state)?;
+    Ok(Json(library.cards().clone()))
+}
+
+async fn get_card(
+    State(state): State<AppState>,
+    Path(card_id): Path<String>,
+) -> Result<Json<Option<Card>>, StatusCode> {
+    let library = lock_library(&state)?;
+    if !library.contains(&card_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(library.get(&card_id).cloned()))
+}
+
+async fn update_card(
+    State(state): State<AppState>,
+    Path(card_id): Path<String>,
+    Json(card): Json<Option<Card>>,
+) -> Result<Json<Option<Card>>, StatusCode> {
+    let mut library = lock_library(&state)?;
+    if let Err(err) = library.update_and_save(&card_id, card.clone(), &state.library_path) {
+        error!("Failed to save library: {err}");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    info!("Updated card {card_id}: {card:?}");
+    Ok(Json(card))
+}
+
+async fn delete_card(
+    State(state): State<AppState>,
+    Path(card_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let mut library = lock_library(&state)?;
+    if !library.contains(&card_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if let Err(err) = library.remove_and_save(&card_id, &state.library_path) {
+        error!("Failed to save library: {err}");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    info!("Deleted card {card_id}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Hands `command` to the service without blocking the async runtime while it is busy.
+fn send_command(state: &AppState, command: Command) -> Result<(), StatusCode> {
+    state.tx_command.try_send(command).map_err(|err| {
+        error!("Failed to send command: {err}");
+        StatusCode::SERVICE_UNAVAILABLE
+    })
+}
+
+async fn play_card(
+    State(state): State<AppState>,
+    Path(card_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    if lock_library(&state)?.get(&card_id).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    send_command(&state, Command::Play(card_id.into()))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn control(
+    State(state): State<AppState>,
+    Path(action): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let card = match action.as_str() {
+        "pause" => Card::Pause,
+        "resume" => Card::Resume,
+        "next" => Card::Next,
+        "previous" => Card::Previous,
+        "volume-up" => Card::VolumeUp,
+        "volume-down" => Card::VolumeDown,
+        "shuffle" => Card::Shuffle,
+        _ => return Err(StatusCode::NOT_FOUND),
+    };
+    send_command(&state, Command::Control(card))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+struct SleepTimerRequest {
+    minutes: u64,
+    #[serde(default)]
+    finish_track: bool,
+}
+
+async fn set_sleep_timer(
+    State(state): State<AppState>,
+    Json(request): Json<SleepTimerRequest>,
+) -> Result<StatusCode, StatusCode> {
+    send_command(
+        &state,
+        Command::SleepTimer {
+            after: Duration::from_secs(request.minutes.saturating_mul(60)),
+            finish_track: request.finish_track,
+        },
+    )?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn cancel_sleep_timer(State(state): State<AppState>) -> Result<StatusCode, StatusCode> {
+    send_command(&state, Command::CancelSleepTimer)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Lets a battery monitor announce that the battery is running low.
+async fn low_battery(State(state): State<AppState>) -> Result<StatusCode, StatusCode> {
+    send_command(&state, Command::Announce(Prompt::LowBattery))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn status(State(state): State<AppState>) -> Result<Json<Status>, StatusCode> {
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    send_command(&state, Command::Status(tx))?;
+    match tokio::task::spawn_blocking(move || rx.recv_timeout(Duration::from_secs(1))).await {
+        Ok(Ok(status)) => Ok(Json(status)),
+        Ok(Err(err)) => {
+            error!("Failed to receive status: {err}");
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+        Err(err) => {
+            error!("Failed to wait for status: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Signals that the manager is shutting down.
+#[derive(Clone)]
+struct Stopping(watch::Receiver<bool>);
+
+/// Streams the events of the box until the manager shuts down.
+async fn events(
+    State(state): State<AppState>,
+    Extension(Stopping(stopping)): Extension<Stopping>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let stopped = WatchStream::new(stopping)
+        .filter(|&stopping| stopping)
+        .map(|_| None);
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(|event| match event {
+        Ok(event) => match sse::Event::default().json_data(&event) {
+            Ok(event) => Some(Ok(event)),
+            Err(err) => {
+                error!("Failed to serialize event: {err}");
+                None
+            }
+        },
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+            warn!("Event stream lagged, skipped {skipped} events");
+            None
+        }
+    });
+    let stream = stream.map(Some).merge(stopped).map_while(|event| event);
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn get_parental(State(state): State<AppState>) -> Result<Json<ParentalConfig>, StatusCode> {
+    let parental = state.parental.lock().map_err(|_| {
+        error!("Failed to lock parental limits");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(parental.clone()))
+}
+
+async fn update_parental(
+    State(state): State<AppState>,
+    Json(update): Json<ParentalConfig>,
+) -> Result<Json<ParentalConfig>, (StatusCode, String)> {
+    if let Err(err) = update.validate() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err.to_string()));
+    }
+    let mut parental = state.parental.lock().map_err(|_| {
+        error!("Failed to lock parental limits");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to lock parental limits".to_string(),
+        )
+    })?;
+    if let Err(err) = update.save_to_config(&state.config_path) {
+        error!("Failed to save parental limits: {err}");
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()));
+    }
+    info!("Updated parental limits: {update:?}");
+    parental.clone_from(&update);
+    Ok(Json(update))
+}
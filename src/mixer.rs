@@ -0,0 +1,189 @@
+use alsa::mixer::{MilliBel, SelemId};
+use tracing::debug;
+
+use crate::error::Error;
+
+/// Applies a volume level, either in hardware or by scaling samples.
+pub trait Mixer {
+    /// Sets the volume to `db` decibels relative to full scale.
+    ///
+    /// Returns the linear gain the `Sink` still has to apply on top.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if the volume could not be set.
+    fn apply(&mut self, db: f32) -> Result<f32, Error>;
+}
+
+/// Applies volume levels with a `Mixer`, trying each new level only once.
+///
+/// A mixer that fails, e.g. because the sound card was unplugged, is therefore not retried,
+/// and its error not reported again, until the level changes.
+pub struct Levels {
+    mixer: Box<dyn Mixer>,
+    last_db: Option<f32>,
+}
+
+impl Levels {
+    #[must_use]
+    pub fn new(mixer: Box<dyn Mixer>) -> Self {
+        Self {
+            mixer,
+            last_db: None,
+        }
+    }
+
+    /// Applies `db` if it differs from the level tried last.
+    ///
+    /// Returns the linear gain the `Sink` still has to apply, or `None` if nothing changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if the mixer failed to set the level.
+    pub fn apply(&mut self, db: f32) -> Result<Option<f32>, Error> {
+        if self.last_db == Some(db) {
+            return Ok(None);
+        }
+        self.last_db = Some(db);
+        self.mixer.apply(db).map(Some)
+    }
+}
+
+/// Scales the samples in the `Sink`, used when there is no usable hardware mixer.
+pub struct SoftwareMixer;
+
+impl Mixer for SoftwareMixer {
+    fn apply(&mut self, db: f32) -> Result<f32, Error> {
+        Ok(10_f32.powf(db / 20.0))
+    }
+}
+
+/// Sets the volume on an ALSA mixer control so the samples keep their full bit depth.
+pub struct AlsaMixer {
+    mixer: alsa::Mixer,
+    control: SelemId,
+}
+
+impl AlsaMixer {
+    /// Opens the mixer control `control` of the ALSA device `device`.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The ALSA device, e.g. `default` or `hw:0`.
+    /// * `control` - The simple mixer control, e.g. `Master` or `PCM`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if the device can't be opened or has no such playback control.
+    pub fn new(device: &str, control: &str) -> Result<Self, Error> {
+        let mixer = alsa::Mixer::new(device, false)?;
+        let selem_id = SelemId::new(control, 0);
+        let Some(selem) = mixer.find_selem(&selem_id) else {
+            return Err(Error::Config(format!(
+                "ALSA device {device} has no mixer control {control}"
+            )));
+        };
+        if !selem.has_playback_volume() {
+            return Err(Error::Config(format!(
+                "ALSA mixer control {control} has no playback volume"
+            )));
+        }
+        Ok(Self {
+            mixer,
+            control: selem_id,
+        })
+    }
+}
+
+impl Mixer for AlsaMixer {
+    fn apply(&mut self, db: f32) -> Result<f32, Error> {
+        let Some(selem) = self.mixer.find_selem(&self.control) else {
+            return Err(Error::Alsa(alsa::Error::unsupported("find_selem")));
+        };
+        let (min, max) = selem.get_playback_db_range();
+        let value = MilliBel::from_db(db).clamp(min, max);
+        debug!("Setting ALSA mixer to {} dB", value.to_db());
+        selem.set_playback_db_all(value, alsa::Round::Floor)?;
+        Ok(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Records the levels it is asked for and fails while `failing` is set.
+    #[derive(Clone, Default)]
+    struct MockMixer {
+        applied: Arc<Mutex<Vec<f32>>>,
+        failing: Arc<Mutex<bool>>,
+    }
+
+    impl Mixer for MockMixer {
+        fn apply(&mut self, db: f32) -> Result<f32, Error> {
+            self.applied.lock().unwrap().push(db);
+            if *self.failing.lock().unwrap() {
+                return Err(Error::Alsa(alsa::Error::unsupported("mock")));
+            }
+            Ok(1.0)
+        }
+    }
+
+    #[test]
+    fn levels_are_applied_once() {
+        let mixer = MockMixer::default();
+        let mut levels = Levels::new(Box::new(mixer.clone()));
+        assert_eq!(levels.apply(-12.0).unwrap(), Some(1.0));
+        assert_eq!(levels.apply(-12.0).unwrap(), None);
+        assert_eq!(levels.apply(-9.0).unwrap(), Some(1.0));
+        assert_eq!(*mixer.applied.lock().unwrap(), [-12.0, -9.0]);
+    }
+
+    #[test]
+    fn failed_levels_are_not_retried() {
+        let mixer = MockMixer::default();
+        *mixer.failing.lock().unwrap() = true;
+        let mut levels = Levels::new(Box::new(mixer.clone()));
+        assert!(levels.apply(-12.0).is_err());
+        assert_eq!(levels.apply(-12.0).unwrap(), None);
+
+        *mixer.failing.lock().unwrap() = false;
+        assert_eq!(levels.apply(-9.0).unwrap(), Some(1.0));
+        assert_eq!(*mixer.applied.lock().unwrap(), [-12.0, -9.0]);
+    }
+
+    #[test]
+    fn levels_pass_on_the_software_gain() {
+        let mut levels = Levels::new(Box::new(SoftwareMixer));
+        assert_eq!(
+            levels
+                .apply(-20.0)
+                .unwrap()
+                .map(|gain| (gain * 10.0).round()),
+            Some(1.0)
+        );
+    }
+
+    fn assert_gain(db: f32, gain: f32) {
+        let applied = SoftwareMixer.apply(db).unwrap();
+        assert!(
+            (applied - gain).abs() < 1e-4,
+            "{db} dB: {applied} != {gain}"
+        );
+    }
+
+    #[test]
+    fn software_mixer_converts_db_to_gain() {
+        assert_gain(0.0, 1.0);
+        assert_gain(-6.0, 0.501_187);
+        assert_gain(-20.0, 0.1);
+        assert_gain(-40.0, 0.01);
+    }
+
+    #[test]
+    fn software_mixer_silences_negative_infinity() {
+        assert_gain(f32::NEG_INFINITY, 0.0);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::card::Card;
+
+/// A request from the manager to start or stop pairing.
+#[derive(Debug, Clone)]
+pub enum PairingRequest {
+    /// Starts pairing if it isn't running, and cancels it otherwise.
+    Toggle,
+    /// Starts pairing, assigning the new card to `target` if given.
+    ///
+    /// With `reassign`, cards that are already bound can be paired as well.
+    Start {
+        target: Option<Card>,
+        reassign: bool,
+    },
+    Cancel,
+}
+
+/// Collects repeated scans of new cards until one of them is confirmed.
+#[derive(Debug)]
+pub struct Pairing {
+    target: Option<Card>,
+    reassign: bool,
+    deadline: Instant,
+    scans: HashMap<Arc<str>, usize>,
+}
+
+impl Pairing {
+    /// Starts pairing, or returns `None` if `timeout` is too long to represent.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - What the paired card is assigned to, if anything.
+    /// * `reassign` - Whether cards that are already bound can be paired.
+    /// * `timeout` - How long to wait for the card to be confirmed.
+    #[must_use]
+    pub fn new(target: Option<Card>, reassign: bool, timeout: Duration) -> Option<Self> {
+        Some(Self {
+            target,
+            reassign,
+            deadline: Instant::now().checked_add(timeout)?,
+            scans: HashMap::new(),
+        })
+    }
+
+    #[must_use]
+    pub const fn target(&self) -> Option<&Card> {
+        self.target.as_ref()
+    }
+
+    #[must_use]
+    pub const fn reassign(&self) -> bool {
+        self.reassign
+    }
+
+    #[must_use]
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Records a scan of `card_id` and returns how often it has been scanned.
+    pub fn scan(&mut self, card_id: Arc<str>) -> usize {
+        let scans = self.scans.entry(card_id).or_default();
+        *scans += 1;
+        *scans
+    }
+}
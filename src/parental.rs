@@ -0,0 +1,193 @@
+use std::{fs, path::Path, sync::Arc};
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Volume and card restrictions for the children using the box.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParentalConfig {
+    /// A hard volume ceiling in decibels that applies at all times.
+    pub max_db: Option<f32>,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+/// A daily time window with stricter limits, e.g. from `19:00` to `07:00`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// The volume ceiling in decibels during quiet hours.
+    pub max_db: Option<f32>,
+    /// If set, only these cards start playback during quiet hours.
+    #[serde(default)]
+    pub allowed_cards: Option<Vec<Arc<str>>>,
+}
+
+impl QuietHours {
+    /// Returns whether `time` lies within the window, which may span midnight.
+    #[must_use]
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl ParentalConfig {
+    fn active_quiet_hours(&self, time: NaiveTime) -> Option<&QuietHours> {
+        self.quiet_hours
+            .as_ref()
+            .filter(|quiet_hours| quiet_hours.contains(time))
+    }
+
+    /// Returns the volume ceiling in decibels at `time`, if any.
+    #[must_use]
+    pub fn max_db(&self, time: NaiveTime) -> Option<f32> {
+        let quiet_max_db = self
+            .active_quiet_hours(time)
+            .and_then(|quiet_hours| quiet_hours.max_db);
+        match (self.max_db, quiet_max_db) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Returns whether the card with `card_id` may start playback at `time`.
+    #[must_use]
+    pub fn allows(&self, card_id: &str, time: NaiveTime) -> bool {
+        self.active_quiet_hours(time)
+            .and_then(|quiet_hours| quiet_hours.allowed_cards.as_ref())
+            .is_none_or(|allowed| allowed.iter().any(|id| id.as_ref() == card_id))
+    }
+
+    /// Returns whether playback that wasn't started by a card, e.g. from the manager, may
+    /// start at `time`.
+    #[must_use]
+    pub fn allows_unlisted(&self, time: NaiveTime) -> bool {
+        self.active_quiet_hours(time)
+            .and_then(|quiet_hours| quiet_hours.allowed_cards.as_ref())
+            .is_none()
+    }
+
+    /// Checks the limits for invalid values.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` describing the first invalid setting.
+    pub fn validate(&self) -> Result<(), Error> {
+        let quiet_max_db = self.quiet_hours.as_ref().and_then(|q| q.max_db);
+        for max_db in [self.max_db, quiet_max_db].into_iter().flatten() {
+            if !max_db.is_finite() || max_db > 0.0 {
+                return Err(Error::Config(
+                    "parental volume ceilings must not be above 0 dB".into(),
+                ));
+            }
+        }
+        if self
+            .quiet_hours
+            .as_ref()
+            .is_some_and(|quiet_hours| quiet_hours.start == quiet_hours.end)
+        {
+            return Err(Error::Config(
+                "parental.quiet_hours start and end must differ".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the limits into the `parental` section of the configuration file, keeping
+    /// all other settings as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the configuration file, which is created if missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if the configuration file could not be read or written.
+    pub fn save_to_config<P: AsRef<str>>(&self, file_path: P) -> Result<(), Error> {
+        let file_path = file_path.as_ref();
+        let mut config: serde_json::Value = if Path::new(file_path).exists() {
+            serde_json::from_str(&fs::read_to_string(file_path).map_err(Error::File)?)
+                .map_err(Error::Deserialize)?
+        } else {
+            serde_json::Value::Object(serde_json::Map::new())
+        };
+        let Some(object) = config.as_object_mut() else {
+            return Err(Error::Config(format!(
+                "{file_path} does not contain a JSON object"
+            )));
+        };
+        object.insert("parental".into(), serde_json::to_value(self)?);
+        fs::write(file_path, serde_json::to_string_pretty(&config)?).map_err(Error::File)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn quiet_hours(start: NaiveTime, end: NaiveTime) -> QuietHours {
+        QuietHours {
+            start,
+            end,
+            max_db: Some(-20.0),
+            allowed_cards: Some(vec!["lullaby".into()]),
+        }
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let quiet_hours = quiet_hours(time(13, 0), time(15, 0));
+        assert!(!quiet_hours.contains(time(12, 59)));
+        assert!(quiet_hours.contains(time(13, 0)));
+        assert!(quiet_hours.contains(time(14, 59)));
+        assert!(!quiet_hours.contains(time(15, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_spanning_midnight() {
+        let quiet_hours = quiet_hours(time(19, 0), time(7, 0));
+        assert!(!quiet_hours.contains(time(18, 59)));
+        assert!(quiet_hours.contains(time(19, 0)));
+        assert!(quiet_hours.contains(time(0, 0)));
+        assert!(quiet_hours.contains(time(6, 59)));
+        assert!(!quiet_hours.contains(time(7, 0)));
+        assert!(!quiet_hours.contains(time(12, 0)));
+    }
+
+    #[test]
+    fn max_db_takes_the_stricter_ceiling() {
+        let parental = ParentalConfig {
+            max_db: Some(-10.0),
+            quiet_hours: Some(quiet_hours(time(19, 0), time(7, 0))),
+        };
+        assert_eq!(parental.max_db(time(12, 0)), Some(-10.0));
+        assert_eq!(parental.max_db(time(22, 0)), Some(-20.0));
+        assert_eq!(ParentalConfig::default().max_db(time(22, 0)), None);
+    }
+
+    #[test]
+    fn allowed_cards_only_apply_during_quiet_hours() {
+        let parental = ParentalConfig {
+            max_db: None,
+            quiet_hours: Some(quiet_hours(time(19, 0), time(7, 0))),
+        };
+        assert!(parental.allows("story", time(12, 0)));
+        assert!(!parental.allows("story", time(2, 0)));
+        assert!(parental.allows("lullaby", time(2, 0)));
+        assert!(parental.allows_unlisted(time(12, 0)));
+        assert!(!parental.allows_unlisted(time(2, 0)));
+    }
+}
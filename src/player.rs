@@ -0,0 +1,446 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use rodio::{Decoder, OutputStreamHandle, Sink, Source};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::{
+    config::FadeConfig,
+    error::Error,
+    fade::{Crossfade, Fader, Shared},
+};
+
+static AUDIO_EXTENSIONS: [&str; 4] = ["flac", "mp3", "ogg", "wav"];
+
+/// A position within a queue of tracks.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Bookmark {
+    pub track: usize,
+    pub position: Duration,
+}
+
+/// Plays an ordered queue of tracks on a `Sink` and keeps track of the current position.
+///
+/// Switching queues, pausing and resuming fade the audio as configured.
+pub struct Player {
+    handle: OutputStreamHandle,
+    sink: Arc<Sink>,
+    fader: Fader,
+    fade: FadeConfig,
+    gain: f32,
+    /// The factor the music is lowered by while a prompt plays.
+    duck: f32,
+    queue: Vec<Arc<str>>,
+    appended: Vec<usize>,
+    /// The part of the first appended track that was skipped, and the silence before it.
+    skipped: Duration,
+    delay: Duration,
+}
+
+impl Player {
+    /// Creates a player on the given output stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `handle` - The output stream to play on.
+    /// * `fade` - The fade durations.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if no sink could be created on the output stream.
+    pub fn new(handle: &OutputStreamHandle, fade: FadeConfig) -> Result<Self, Error> {
+        Ok(Self {
+            handle: handle.clone(),
+            sink: Arc::new(Sink::try_new(handle)?),
+            fader: Fader::new(),
+            fade,
+            gain: 1.0,
+            duck: 1.0,
+            queue: vec![],
+            appended: vec![],
+            skipped: Duration::ZERO,
+            delay: Duration::ZERO,
+        })
+    }
+
+    #[must_use]
+    pub fn sink(&self) -> &Sink {
+        &self.sink
+    }
+
+    /// Sets the volume factor of the playback, independent of any running fade.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+        self.sink.set_volume(self.gain * self.duck);
+    }
+
+    /// Lowers the playback by `factor` on top of the gain, e.g. while a prompt plays.
+    pub fn duck(&mut self, factor: f32) {
+        self.duck = factor;
+        self.sink.set_volume(self.gain * self.duck);
+    }
+
+    /// Fades the playback out over `duration` without pausing it.
+    pub fn fade_out(&self, duration: Duration) {
+        self.fader.fade_to(0.0, duration);
+    }
+
+    /// Fades back in after [`Player::fade_out`].
+    pub fn cancel_fade(&self) {
+        if !self.sink.is_paused() {
+            self.fader
+                .fade_to(1.0, Duration::from_millis(self.fade.fade_in_ms));
+        }
+    }
+
+    /// Returns the index of the track that is currently playing.
+    #[must_use]
+    pub fn current(&self) -> Option<usize> {
+        let remaining = self.sink.len();
+        if remaining == 0 || remaining > self.appended.len() {
+            return None;
+        }
+        self.appended.get(self.appended.len() - remaining).copied()
+    }
+
+    /// Returns the file of the track that is currently playing.
+    #[must_use]
+    pub fn track(&self) -> Option<Arc<str>> {
+        self.current()
+            .and_then(|index| self.queue.get(index).cloned())
+    }
+
+    /// Returns the current track and the playback offset within it.
+    #[must_use]
+    pub fn bookmark(&self) -> Option<Bookmark> {
+        self.current().map(|track| Bookmark {
+            track,
+            position: self.position(),
+        })
+    }
+
+    /// Returns the playback offset within the current track.
+    #[must_use]
+    pub fn position(&self) -> Duration {
+        let position = self.sink.get_pos();
+        if self.sink.len() == self.appended.len() {
+            (position + self.skipped).saturating_sub(self.delay)
+        } else {
+            position
+        }
+    }
+
+    /// Replaces the queue with `tracks` and starts playing the first one.
+    ///
+    /// Directories are expanded to the audio files they contain, sorted by track number.
+    pub fn play(&mut self, tracks: Vec<Arc<str>>) {
+        self.play_at(tracks, None);
+    }
+
+    /// Replaces the queue with `tracks` and starts playing at `bookmark`, if any.
+    pub fn play_at(&mut self, tracks: Vec<Arc<str>>, bookmark: Option<Bookmark>) {
+        self.queue = tracks.iter().flat_map(expand).collect();
+        match bookmark {
+            Some(bookmark) if bookmark.track < self.queue.len() => {
+                debug!("Resuming at {bookmark:?}");
+                self.play_from_position(bookmark.track, bookmark.position);
+            }
+            _ => self.play_from(0),
+        }
+    }
+
+    /// Plays the current queue again from the first track.
+    pub fn restart(&mut self) {
+        self.play_from(0);
+    }
+
+    pub fn next(&mut self) {
+        match self.current() {
+            Some(index) if index + 1 < self.queue.len() => self.play_from(index + 1),
+            _ => {
+                debug!("No next track");
+                self.stop();
+            }
+        }
+    }
+
+    pub fn previous(&mut self) {
+        let index = self.current().unwrap_or(self.queue.len());
+        self.play_from(index.saturating_sub(1));
+    }
+
+    /// Fades out and pauses the playback.
+    pub fn pause(&self) {
+        let fade_out = Duration::from_millis(self.fade.fade_out_ms);
+        if fade_out.is_zero() || self.sink.is_paused() {
+            self.sink.pause();
+            return;
+        }
+        let fade = self.fader.fade_to(0.0, fade_out);
+        let sink = self.sink.clone();
+        let fader = self.fader.clone();
+        thread::spawn(move || {
+            thread::sleep(fade_out);
+            // A resume or another queue during the fade wins over the pause.
+            if fader.is_current(fade) {
+                sink.pause();
+            }
+        });
+    }
+
+    /// Resumes the playback and fades it in.
+    pub fn resume(&self) {
+        if self.sink.is_paused() {
+            self.fader.set(0.0);
+        }
+        self.fader
+            .fade_to(1.0, Duration::from_millis(self.fade.fade_in_ms));
+        self.sink.play();
+    }
+
+    fn play_from(&mut self, index: usize) {
+        self.play_from_position(index, Duration::ZERO);
+    }
+
+    /// Replaces the sink with a new one and fades the old one out in the background.
+    ///
+    /// Returns how long the new sink should stay silent before playing.
+    fn replace_sink(&mut self) -> Duration {
+        let fade_out = Duration::from_millis(self.fade.fade_out_ms);
+        if fade_out.is_zero() || self.sink.empty() || self.sink.is_paused() {
+            self.stop();
+            return Duration::ZERO;
+        }
+        let sink = match Sink::try_new(&self.handle) {
+            Ok(sink) => sink,
+            Err(err) => {
+                error!("Failed to create sink: {err}");
+                self.stop();
+                return Duration::ZERO;
+            }
+        };
+        sink.set_volume(self.gain * self.duck);
+        let old_sink = std::mem::replace(&mut self.sink, Arc::new(sink));
+        let old_fader = std::mem::take(&mut self.fader);
+        old_fader.fade_to(0.0, fade_out);
+        thread::spawn(move || {
+            thread::sleep(fade_out);
+            old_sink.stop();
+        });
+        if self.fade.crossfade {
+            Duration::ZERO
+        } else {
+            fade_out
+        }
+    }
+
+    fn stop(&mut self) {
+        self.sink.stop();
+        self.sink.play();
+        self.fader.set(1.0);
+    }
+
+    fn play_from_position(&mut self, index: usize, position: Duration) {
+        let delay = self.replace_sink();
+        self.appended.clear();
+        self.skipped = position;
+        self.delay = delay;
+        let mut sources = vec![];
+        for (i, file_path) in self.queue.iter().enumerate().skip(index) {
+            if let Some(source) = decode(file_path) {
+                let source = if i == index && !position.is_zero() {
+                    match seek(file_path, source, position) {
+                        Some(source) => source,
+                        None => continue,
+                    }
+                } else {
+                    Box::new(source)
+                };
+                sources.push(Shared::new(source));
+                self.appended.push(i);
+            }
+        }
+        if self.appended.first() != Some(&index) {
+            self.skipped = Duration::ZERO;
+        }
+        let fade_in = Duration::from_millis(self.fade.fade_in_ms);
+        let crossfade = Duration::from_millis(self.fade.track_crossfade_ms);
+        let mut faded_in = false;
+        for (i, source) in sources.iter().enumerate() {
+            let next = sources.get(i + 1).filter(|next| {
+                crossfade > Duration::ZERO
+                    && next.channels() == source.channels()
+                    && next.sample_rate() == source.sample_rate()
+            });
+            let mixes_next = next.is_some();
+            let source = Crossfade::new(source.clone(), next.cloned(), crossfade);
+            if i == 0 {
+                self.sink
+                    .append(self.fader.apply(source.fade_in(fade_in).delay(delay)));
+            } else if faded_in {
+                self.sink.append(self.fader.apply(source));
+            } else {
+                self.sink
+                    .append(self.fader.apply(source.fade_in(crossfade)));
+            }
+            faded_in = mixes_next;
+        }
+    }
+}
+
+/// Returns whether `path` is a file in one of the supported audio formats.
+#[must_use]
+pub fn is_audio_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+fn expand(path: &Arc<str>) -> Vec<Arc<str>> {
+    if !Path::new(path.as_ref()).is_dir() {
+        return vec![path.clone()];
+    }
+    let entries = match fs::read_dir(path.as_ref()) {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("Failed to read directory {path}: {err}");
+            return vec![];
+        }
+    };
+    let mut files: Vec<Arc<str>> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| is_audio_file(path))
+        .map(|path| Arc::from(path.to_string_lossy().as_ref()))
+        .collect();
+    files.sort_by(|a, b| natural_cmp(a, b));
+    debug!("Found {} tracks in {path}", files.len());
+    files
+}
+
+/// Compares two strings so that embedded numbers are ordered by value, e.g. `2 - Intro` before `10 - Outro`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                let ordering = x
+                    .trim_start_matches('0')
+                    .len()
+                    .cmp(&y.trim_start_matches('0').len())
+                    .then_with(|| x.trim_start_matches('0').cmp(y.trim_start_matches('0')));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        number.push(c);
+    }
+    number
+}
+
+/// Moves `source` to `position`, seeking if the format supports it and decoding up to it
+/// otherwise.
+fn seek(
+    file_path: &str,
+    mut source: Decoder<BufReader<File>>,
+    position: Duration,
+) -> Option<Box<dyn Source<Item = i16> + Send>> {
+    match source.try_seek(position) {
+        Ok(()) => Some(Box::new(source)),
+        Err(err) => {
+            debug!("Failed to seek in {file_path}, skipping instead: {err}");
+            let source = if err.source_intact() {
+                source
+            } else {
+                decode(file_path)?
+            };
+            Some(Box::new(source.skip_duration(position)))
+        }
+    }
+}
+
+fn decode(file_path: &str) -> Option<Decoder<BufReader<File>>> {
+    let file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(err) => {
+            error!("Failed to open file {file_path}: {err}");
+            return None;
+        }
+    };
+    match Decoder::new(BufReader::new(file)) {
+        Ok(source) => Some(source),
+        Err(err) => {
+            error!("Failed to decode file {file_path}: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_cmp_orders_numbers_by_value() {
+        assert_eq!(natural_cmp("2 - Intro", "10 - Outro"), Ordering::Less);
+        assert_eq!(natural_cmp("10 - Outro", "2 - Intro"), Ordering::Greater);
+        assert_eq!(natural_cmp("Track 9.mp3", "Track 10.mp3"), Ordering::Less);
+    }
+
+    #[test]
+    fn natural_cmp_ignores_leading_zeros() {
+        assert_eq!(natural_cmp("01", "1"), Ordering::Equal);
+        assert_eq!(natural_cmp("002", "10"), Ordering::Less);
+        assert_eq!(natural_cmp("09 b", "9 a"), Ordering::Greater);
+    }
+
+    #[test]
+    fn natural_cmp_ignores_case() {
+        assert_eq!(natural_cmp("a.mp3", "B.mp3"), Ordering::Less);
+        assert_eq!(natural_cmp("Track", "track"), Ordering::Equal);
+    }
+
+    #[test]
+    fn natural_cmp_orders_prefixes_first() {
+        assert_eq!(natural_cmp("Track", "Track 1"), Ordering::Less);
+        assert_eq!(natural_cmp("", "a"), Ordering::Less);
+    }
+
+    #[test]
+    fn natural_cmp_sorts_a_folder() {
+        let mut files = vec!["10.mp3", "2.mp3", "1.mp3", "02b.mp3"];
+        files.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(files, ["1.mp3", "2.mp3", "02b.mp3", "10.mp3"]);
+    }
+}
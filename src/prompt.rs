@@ -0,0 +1,254 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    thread,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use rodio::{Decoder, OutputStreamHandle, Sink};
+use tracing::{debug, error, warn};
+
+use crate::{admin::AdminAction, config::SoundsConfig, error::Error};
+
+/// A notification about the state of the box, played from the sound pack or spoken.
+#[derive(Debug, Clone)]
+pub enum Prompt {
+    Success,
+    Failure,
+    CardUnknown,
+    PairingStarted,
+    PairingCancelled,
+    PairingTimedOut,
+    /// Asks for another scan of the card being paired.
+    PairingScan,
+    HotspotOn {
+        ssid: Arc<str>,
+    },
+    HotspotOff,
+    LowBattery,
+    VolumeMax,
+    SleepTimerSet {
+        minutes: u64,
+    },
+    Admin(AdminAction),
+    AdminTimedOut,
+}
+
+impl Prompt {
+    /// Returns the name of the prompt's file in the sound pack, without the extension.
+    #[must_use]
+    pub fn name(&self) -> String {
+        match self {
+            Self::Success => "positive_confirmation".into(),
+            Self::Failure => "negative_confirmation".into(),
+            Self::CardUnknown => "card_unknown".into(),
+            Self::PairingStarted => "pairing_started".into(),
+            Self::PairingCancelled => "pairing_cancelled".into(),
+            Self::PairingTimedOut => "pairing_timed_out".into(),
+            Self::PairingScan => "pairing_scan".into(),
+            Self::HotspotOn { .. } => "hotspot_on".into(),
+            Self::HotspotOff => "hotspot_off".into(),
+            Self::LowBattery => "low_battery".into(),
+            Self::VolumeMax => "volume_max".into(),
+            Self::SleepTimerSet { .. } => "sleep_timer_set".into(),
+            Self::Admin(action) => format!("admin/{}", action.name()),
+            Self::AdminTimedOut => "admin/timed_out".into(),
+        }
+    }
+
+    /// Returns the text spoken for the prompt if the sound pack doesn't have it.
+    #[must_use]
+    pub fn text(&self) -> String {
+        match self {
+            Self::Success => "Done.".into(),
+            Self::Failure => "Something went wrong.".into(),
+            Self::CardUnknown => "I don't know this card.".into(),
+            Self::PairingStarted => "Place the new card on the box.".into(),
+            Self::PairingCancelled => "Pairing cancelled.".into(),
+            Self::PairingTimedOut => "Pairing timed out.".into(),
+            Self::PairingScan => "Once more, please.".into(),
+            Self::HotspotOn { ssid } => format!("Hotspot on. Connect to {ssid}."),
+            Self::HotspotOff => "Hotspot off.".into(),
+            Self::LowBattery => "The battery is low.".into(),
+            Self::VolumeMax => "This is the loudest volume.".into(),
+            Self::SleepTimerSet { minutes: 1 } => "Sleeping in one minute.".into(),
+            Self::SleepTimerSet { minutes } => format!("Sleeping in {minutes} minutes."),
+            Self::Admin(action) => format!("Admin: {}.", action.name().replace('_', " ")),
+            Self::AdminTimedOut => "Admin mode ended.".into(),
+        }
+    }
+
+    /// Returns the prompts that don't depend on the state of the box.
+    fn fixed() -> Vec<Self> {
+        let mut prompts = vec![
+            Self::Success,
+            Self::Failure,
+            Self::CardUnknown,
+            Self::PairingStarted,
+            Self::PairingCancelled,
+            Self::PairingTimedOut,
+            Self::PairingScan,
+            Self::HotspotOff,
+            Self::LowBattery,
+            Self::VolumeMax,
+            Self::AdminTimedOut,
+        ];
+        prompts.extend(AdminAction::all().into_iter().map(Self::Admin));
+        prompts
+    }
+}
+
+/// Plays prompts on their own `Sink`, so they can be heard over the music.
+///
+/// Prompts are queued on a thread of their own, which waits for the text-to-speech engine
+/// without holding up the service.
+pub struct Prompter {
+    sink: Arc<Sink>,
+    tx: Sender<Job>,
+}
+
+/// Work for the prompter's thread, done in the order it was queued.
+enum Job {
+    /// Renders a prompt missing from the sound pack ahead of time.
+    Prerender(Prompt),
+    Play(Prompt),
+    PlayFile(PathBuf),
+}
+
+impl Prompter {
+    /// Creates a prompter on the given output stream and starts rendering the prompts that
+    /// are missing from the sound pack.
+    ///
+    /// # Arguments
+    ///
+    /// * `handle` - The output stream to play on.
+    /// * `config` - The sound pack and text-to-speech settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error` if no sink could be created on the output stream.
+    pub fn new(handle: &OutputStreamHandle, config: SoundsConfig) -> Result<Self, Error> {
+        let sink = Arc::new(Sink::try_new(handle)?);
+        let (tx, rx) = crossbeam_channel::unbounded();
+        if config.tts.is_some() {
+            for prompt in Prompt::fixed() {
+                // The thread is started below, so the channel is still connected.
+                let _ = tx.send(Job::Prerender(prompt));
+            }
+        }
+        let worker_sink = sink.clone();
+        thread::spawn(move || work(&worker_sink, &config, &rx));
+        Ok(Self { sink, tx })
+    }
+
+    /// Returns whether a prompt is playing.
+    #[must_use]
+    pub fn is_playing(&self) -> bool {
+        !self.sink.empty()
+    }
+
+    pub fn set_gain(&self, gain: f32) {
+        self.sink.set_volume(gain);
+    }
+
+    /// Plays `prompt` from the sound pack, or speaks it if the sound pack lacks it.
+    pub fn play(&self, prompt: &Prompt) {
+        debug!("Prompt: {prompt:?}");
+        self.send(Job::Play(prompt.clone()));
+    }
+
+    /// Plays the sound file at `file_path` after the prompts already queued.
+    pub fn play_file<P: AsRef<Path>>(&self, file_path: P) {
+        self.send(Job::PlayFile(file_path.as_ref().to_path_buf()));
+    }
+
+    fn send(&self, job: Job) {
+        if self.tx.send(job).is_err() {
+            error!("Prompt thread stopped");
+        }
+    }
+}
+
+fn work(sink: &Sink, config: &SoundsConfig, rx: &Receiver<Job>) {
+    for job in rx {
+        match job {
+            Job::Prerender(prompt) => {
+                if !pack_file(config, &prompt).is_file() {
+                    speak(config, &prompt.text());
+                }
+            }
+            Job::Play(prompt) => {
+                let file_path = pack_file(config, &prompt);
+                if file_path.is_file() {
+                    append(sink, &file_path);
+                } else if let Some(spoken) = speak(config, &prompt.text()) {
+                    append(sink, &spoken);
+                } else {
+                    warn!("No sound for prompt: {}", file_path.to_string_lossy());
+                }
+            }
+            Job::PlayFile(file_path) => append(sink, &file_path),
+        }
+    }
+}
+
+fn pack_file(config: &SoundsConfig, prompt: &Prompt) -> PathBuf {
+    Path::new(config.dir.as_ref()).join(format!("{}.wav", prompt.name()))
+}
+
+fn append(sink: &Sink, file_path: &Path) {
+    let source = File::open(file_path)
+        .map_err(Error::File)
+        .and_then(|file| Decoder::new(BufReader::new(file)).map_err(Error::Decoder));
+    match source {
+        Ok(source) => sink.append(source),
+        Err(err) => error!("Failed to play {}: {err}", file_path.to_string_lossy()),
+    }
+}
+
+/// Renders `text` with the text-to-speech command, reusing earlier renderings.
+fn speak(config: &SoundsConfig, text: &str) -> Option<PathBuf> {
+    let tts = config.tts.as_ref()?;
+    let mut hasher = DefaultHasher::new();
+    (&tts.command, &tts.args, text).hash(&mut hasher);
+    let cache = Path::new(config.dir.as_ref()).join(".tts");
+    let file_path = cache.join(format!("{:016x}.wav", hasher.finish()));
+    if file_path.is_file() {
+        return Some(file_path);
+    }
+    if let Err(err) = fs::create_dir_all(&cache) {
+        error!("Failed to create {}: {err}", cache.to_string_lossy());
+        return None;
+    }
+    debug!("Speaking: {text}");
+    // Only complete renderings end up in the cache.
+    let partial = file_path.with_extension("part");
+    let status = Command::new(tts.command.as_ref())
+        .args(tts.args.iter().map(AsRef::as_ref))
+        .arg("-w")
+        .arg(&partial)
+        .arg(text)
+        .status();
+    match status {
+        Ok(status) if status.success() => match fs::rename(&partial, &file_path) {
+            Ok(()) => Some(file_path),
+            Err(err) => {
+                error!("Failed to cache {}: {err}", file_path.to_string_lossy());
+                None
+            }
+        },
+        Ok(status) => {
+            error!("{} failed with {status}", tts.command);
+            None
+        }
+        Err(err) => {
+            error!("Failed to run {}: {err}", tts.command);
+            None
+        }
+    }
+}
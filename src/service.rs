@@ -1,28 +1,91 @@
 use std::{
-    fs::File,
-    io::BufReader,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
 };
 
+use chrono::Local;
 use crossbeam_channel::{Receiver, Sender};
-use rodio::{Decoder, OutputStream, Sink};
-use tracing::{debug, error, info};
+use rodio::OutputStream;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, warn};
 use wifi_rs::{prelude::*, WiFi};
 
-use crate::{card::Card, error::Error, library::Library, manager};
+use crate::{
+    admin::{Admin, AdminAction},
+    card::Card,
+    card_reader::{CardEvent, CardReader, ReaderStatus},
+    config::{self, EndOfQueue, HotspotConfig, RescanPolicy, VolumeBackend},
+    error::Error,
+    event::Event,
+    library::Library,
+    manager,
+    mixer::{AlsaMixer, Levels, Mixer, SoftwareMixer},
+    pairing::{Pairing, PairingRequest},
+    parental::ParentalConfig,
+    player::Player,
+    prompt::{Prompt, Prompter},
+    volume::Volume,
+};
+
+static PLAYER_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A request from the manager to the service.
+#[derive(Debug)]
+pub enum Command {
+    /// Plays the card with the given ID as if it had been scanned.
+    Play(Arc<str>),
+    /// Runs a control card such as `Pause`, `Next` or `VolumeUp`.
+    Control(Card),
+    /// Pauses playback after the given time, optionally letting the current track finish.
+    SleepTimer {
+        after: Duration,
+        finish_track: bool,
+    },
+    CancelSleepTimer,
+    /// Plays a prompt reported from outside the box, such as a low battery.
+    Announce(Prompt),
+    /// Replies with the current playback status.
+    Status(Sender<Status>),
+}
 
-static SUCCESS_SOUND: &str = "sounds/positive_confirmation.wav";
-static FAILURE_SOUND: &str = "sounds/negative_confirmation.wav";
+/// A snapshot of the playback state.
+#[derive(Debug, Serialize, Clone)]
+pub struct Status {
+    pub card: Option<Arc<str>>,
+    pub track: Option<Arc<str>>,
+    pub position: Duration,
+    pub volume_db: f32,
+    pub paused: bool,
+    pub pairing: bool,
+    pub sleep_remaining: Option<Duration>,
+    pub reader: ReaderStatus,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SleepTimer {
+    Waiting {
+        deadline: Instant,
+        finish_track: bool,
+    },
+    /// Waits for the number of queued tracks to change.
+    FinishingTrack {
+        remaining: usize,
+    },
+    FadingOut {
+        started: Instant,
+    },
+}
 
-fn toggle_hotspot(enable: bool) -> Result<(), Error> {
-    let config = Config {
-        interface: Some("wlp59s0"),
+fn toggle_hotspot(config: &HotspotConfig, enable: bool) -> Result<(), Error> {
+    let wifi_config = Config {
+        interface: Some(&config.interface),
     };
 
-    let mut hotspot = WiFi::new(Some(config));
+    let mut hotspot = WiFi::new(Some(wifi_config));
 
     if enable {
-        match hotspot.create_hotspot("MARLIN", "M4rl!nB0x", None) {
+        match hotspot.create_hotspot(&config.ssid, &config.password, None) {
             Ok(true) => info!("Hotspot enabled"),
             Ok(false) => info!("Hotspot already enabled"),
             Err(err) => {
@@ -44,157 +107,868 @@ fn toggle_hotspot(enable: bool) -> Result<(), Error> {
     Ok(())
 }
 
-fn play_card(card: &Card, sink: &Sink) {
+fn play_card(card: &Card, player: &mut Player) {
     match card {
-        Card::Play(music_file) => {
-            play_sound(sink, music_file);
+        Card::Play(music_file) | Card::Audiobook(music_file) => {
+            player.play(vec![music_file.clone()]);
         }
-        Card::Pause => sink.pause(),
-        Card::Resume => sink.play(),
-        Card::Next | Card::Previous => sink.stop(),
-        Card::ToggleHotspot | Card::Shuffle => {}
+        Card::Playlist(music_files) => player.play(music_files.clone()),
+        Card::Pause => player.pause(),
+        Card::Resume => player.resume(),
+        Card::Next => player.next(),
+        Card::Previous => player.previous(),
+        Card::ToggleHotspot
+        | Card::Shuffle
+        | Card::VolumeUp
+        | Card::VolumeDown
+        | Card::SleepTimer(_)
+        | Card::Admin => {}
+    }
+}
 
-        // TODO: Volume management. Currently the volume is set independetly from the OS which leads to a horrible quality decrease.
-        Card::VolumeUp => sink.set_volume(sink.volume() + 1.0),
-        Card::VolumeDown => sink.set_volume(sink.volume() - 1.0),
+fn store_bookmark(library: &mut Library, library_path: &str, card_id: &str, player: &Player) {
+    let bookmark = player.bookmark();
+    debug!("Storing bookmark for {card_id}: {bookmark:?}");
+    library.set_bookmark(card_id, bookmark);
+    if let Err(err) = library.save_to_file(library_path) {
+        error!("Failed to save bookmark: {err}");
     }
 }
 
-fn play_sound(sink: &Sink, file_path: &str) {
-    let file = match File::open(file_path) {
-        Ok(file) => file,
-        Err(err) => {
-            error!("Failed to open file {file_path}: {err}");
+struct Service<'a> {
+    config: &'a config::Config,
+    library: &'a Arc<Mutex<Library>>,
+    player: Player,
+    prompter: Prompter,
+    ducked: bool,
+    volume: Volume,
+    levels: Levels,
+    parental: Arc<Mutex<ParentalConfig>>,
+    tx_pairing: Arc<Sender<PairingRequest>>,
+    tx_command: Arc<Sender<Command>>,
+    tx_manager_shutdown: Arc<Sender<()>>,
+    rx_manager_shutdown: Arc<Receiver<()>>,
+    events: broadcast::Sender<Event>,
+    last_track: Option<Arc<str>>,
+    hotspot_enabled: bool,
+    pairing: Option<Pairing>,
+    admin: Option<Admin>,
+    current_card: Option<Arc<str>>,
+    resumable: Option<Arc<str>>,
+    /// The card whose removal paused playback in hold mode.
+    lifted: Option<Arc<str>>,
+    reader: ReaderStatus,
+    /// Whether a card's queue was started and its end-of-queue action is still due.
+    queue_active: bool,
+    sleep_timer: Option<SleepTimer>,
+}
+
+impl Service<'_> {
+    fn emit(&self, event: Event) {
+        debug!("Event: {event:?}");
+        // Sending only fails if nobody is subscribed, which is the normal case without the manager.
+        let _ = self.events.send(event);
+    }
+
+    fn emit_error(&self, message: String) {
+        self.emit(Event::Error { message });
+    }
+
+    fn on_pairing_request(&mut self, request: PairingRequest) {
+        debug!("Pairing request: {request:?}");
+        match request {
+            PairingRequest::Toggle if self.pairing.is_some() => self.cancel_pairing(),
+            PairingRequest::Toggle => self.start_pairing(None, false),
+            PairingRequest::Start { target, reassign } => self.start_pairing(target, reassign),
+            PairingRequest::Cancel => self.cancel_pairing(),
+        }
+    }
+
+    fn start_pairing(&mut self, target: Option<Card>, reassign: bool) {
+        let timeout = Duration::from_secs(self.config.pairing.timeout_seconds);
+        let Some(pairing) = Pairing::new(target, reassign, timeout) else {
+            warn!("Pairing timeout too long: {timeout:?}");
             return;
+        };
+        info!("Pairing mode enabled for {timeout:?}");
+        self.pairing = Some(pairing);
+        self.prompter.play(&Prompt::PairingStarted);
+        self.emit(Event::PairingToggled { enabled: true });
+    }
+
+    fn cancel_pairing(&mut self) {
+        if self.pairing.take().is_some() {
+            info!("Pairing cancelled");
+            self.prompter.play(&Prompt::PairingCancelled);
+            self.emit(Event::PairingToggled { enabled: false });
         }
-    };
-    let file = BufReader::new(file);
-    let source = match Decoder::new(file) {
-        Ok(source) => source,
-        Err(err) => {
-            error!("Failed to decode file {file_path}: {err}");
+    }
+
+    fn poll_pairing(&mut self) {
+        if self.pairing.as_ref().is_some_and(Pairing::is_expired) {
+            info!("Pairing timed out");
+            self.pairing = None;
+            self.prompter.play(&Prompt::PairingTimedOut);
+            self.emit(Event::PairingTimedOut);
+            self.emit(Event::PairingToggled { enabled: false });
+        }
+    }
+
+    /// Lowers the music while a prompt plays, and restores it afterwards.
+    fn poll_prompts(&mut self) {
+        let ducked = self.prompter.is_playing();
+        if ducked == self.ducked {
             return;
         }
-    };
-    sink.stop();
-    sink.append(source);
+        let factor = if ducked {
+            10_f32.powf(self.config.sounds.duck_db / 20.0)
+        } else {
+            1.0
+        };
+        self.player.duck(factor);
+        self.ducked = ducked;
+    }
+
+    /// Publishes track changes of the player and handles the end of the queue.
+    fn poll_player(&mut self) {
+        let track = self.player.track();
+        if track == self.last_track {
+            return;
+        }
+        if let Some(finished) = self.last_track.take() {
+            self.emit(Event::TrackFinished { track: finished });
+        }
+        if let Some(started) = &track {
+            self.emit(Event::TrackStarted {
+                track: started.clone(),
+            });
+        }
+        self.last_track = track;
+
+        if self.last_track.is_none() && std::mem::take(&mut self.queue_active) {
+            self.emit(Event::QueueFinished);
+            self.end_of_queue();
+        }
+    }
+
+    fn end_of_queue(&mut self) {
+        let repeat = match &self.config.playback.end_of_queue {
+            EndOfQueue::Stop => false,
+            EndOfQueue::Repeat => true,
+            EndOfQueue::Chime(file_path) => {
+                self.prompter.play_file(file_path.as_ref());
+                false
+            }
+            EndOfQueue::Sleep { minutes } => {
+                if self.sleep_timer.is_none() {
+                    self.set_sleep_timer(
+                        Duration::from_secs(minutes.saturating_mul(60)),
+                        self.config.sleep.finish_track,
+                    );
+                }
+                true
+            }
+        };
+        if repeat {
+            debug!("Repeating queue");
+            self.player.restart();
+            self.queue_active = true;
+        } else {
+            info!("Queue finished");
+        }
+    }
+
+    fn set_sleep_timer(&mut self, after: Duration, finish_track: bool) {
+        if after.is_zero() {
+            self.cancel_sleep_timer();
+            return;
+        }
+        let Some(deadline) = Instant::now().checked_add(after) else {
+            warn!("Sleep timer too long: {after:?}");
+            return;
+        };
+        info!("Sleep timer set to {after:?}");
+        self.clear_sleep_timer();
+        self.prompter.play(&Prompt::SleepTimerSet {
+            minutes: after.as_secs().div_ceil(60),
+        });
+        self.sleep_timer = Some(SleepTimer::Waiting {
+            deadline,
+            finish_track,
+        });
+        self.emit(Event::SleepTimerSet { after });
+    }
+
+    fn cancel_sleep_timer(&mut self) {
+        if self.clear_sleep_timer() {
+            info!("Sleep timer cancelled");
+            self.emit(Event::SleepTimerCancelled);
+        }
+    }
+
+    fn sleep_remaining(&self) -> Option<Duration> {
+        match self.sleep_timer? {
+            SleepTimer::Waiting { deadline, .. } => {
+                Some(deadline.saturating_duration_since(Instant::now()))
+            }
+            SleepTimer::FinishingTrack { .. } | SleepTimer::FadingOut { .. } => {
+                Some(Duration::ZERO)
+            }
+        }
+    }
+
+    /// Returns how long the service may block before it has to look at the player again.
+    fn next_wakeup(&self) -> Option<Duration> {
+        let playing = self.last_track.is_some() && !self.player.sink().is_paused();
+        let poll = (playing || self.ducked).then_some(PLAYER_POLL_INTERVAL);
+        let sleep_timer = match self.sleep_timer {
+            None => None,
+            Some(SleepTimer::Waiting { deadline, .. }) => {
+                Some(deadline.saturating_duration_since(Instant::now()))
+            }
+            Some(SleepTimer::FinishingTrack { .. }) => Some(PLAYER_POLL_INTERVAL),
+            Some(SleepTimer::FadingOut { started }) => {
+                let fade = Duration::from_secs(self.config.sleep.fade_seconds);
+                Some(fade.saturating_sub(started.elapsed()))
+            }
+        };
+        let pairing = self.pairing.as_ref().map(Pairing::remaining);
+        let admin = self.admin.as_ref().map(Admin::remaining);
+        [poll, sleep_timer, pairing, admin]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    fn poll_sleep_timer(&mut self) -> Result<(), Error> {
+        let Some(sleep_timer) = self.sleep_timer else {
+            return Ok(());
+        };
+        let playing = !self.player.sink().empty() && !self.player.sink().is_paused();
+        match sleep_timer {
+            SleepTimer::Waiting { deadline, .. } if Instant::now() < deadline => {}
+            _ if !playing => {
+                debug!("Sleep timer elapsed without playback");
+                self.clear_sleep_timer();
+                self.emit(Event::SleepTimerElapsed);
+            }
+            SleepTimer::Waiting {
+                finish_track: true, ..
+            } => {
+                info!("Sleep timer elapsed, finishing the current track");
+                self.sleep_timer = Some(SleepTimer::FinishingTrack {
+                    remaining: self.player.sink().len(),
+                });
+            }
+            SleepTimer::Waiting { .. } => {
+                info!("Sleep timer elapsed, fading out");
+                self.player
+                    .fade_out(Duration::from_secs(self.config.sleep.fade_seconds));
+                self.sleep_timer = Some(SleepTimer::FadingOut {
+                    started: Instant::now(),
+                });
+            }
+            SleepTimer::FinishingTrack { remaining } => {
+                if self.player.sink().len() != remaining {
+                    self.fall_asleep()?;
+                }
+            }
+            SleepTimer::FadingOut { started } => {
+                if started.elapsed() >= Duration::from_secs(self.config.sleep.fade_seconds) {
+                    self.fall_asleep()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fall_asleep(&mut self) -> Result<(), Error> {
+        info!("Sleep timer paused playback");
+        let library = self.library;
+        let mut library_lock = library.lock()?;
+        self.play(&mut library_lock, None, Card::Pause);
+        self.sleep_timer = None;
+        self.emit(Event::SleepTimerElapsed);
+        Ok(())
+    }
+
+    /// Stops the sleep timer and undoes its fade-out, leaving other fades such as a pause alone.
+    ///
+    /// Returns whether a sleep timer was running.
+    fn clear_sleep_timer(&mut self) -> bool {
+        match self.sleep_timer.take() {
+            Some(SleepTimer::FadingOut { .. }) => {
+                self.player.cancel_fade();
+                true
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn on_card(&mut self, card_id: Arc<str>) -> Result<(), Error> {
+        debug!("Card ID: {card_id}");
+
+        let library = self.library;
+        let mut library_lock = library.lock()?;
+        if self.lifted.as_ref() == Some(&card_id) {
+            info!("Card placed back, resuming");
+            self.lifted = None;
+            self.play(&mut library_lock, None, Card::Resume);
+            return Ok(());
+        }
+        let card = library_lock.get(&card_id).cloned();
+        self.emit(Event::CardScanned {
+            id: card_id.clone(),
+            known: card.is_some(),
+        });
+
+        if self.admin.is_some() && !matches!(card, Some(Card::Admin)) {
+            self.administer(&mut library_lock, card_id);
+            return Ok(());
+        }
+
+        if self.pairing.as_ref().is_some_and(Pairing::reassign) {
+            self.pair(&mut library_lock, card_id);
+            return Ok(());
+        }
+
+        let is_current =
+            self.current_card.as_ref() == Some(&card_id) && self.player.track().is_some();
+        if is_current && self.player.sink().is_paused() {
+            info!("Current card placed again, resuming");
+            self.play(&mut library_lock, None, Card::Resume);
+            return Ok(());
+        }
+        // Shuffle picks another track every time, so it has nothing to re-scan.
+        if is_current && !matches!(card, Some(Card::Shuffle)) {
+            let control = match self.config.playback.rescan {
+                RescanPolicy::Ignore => {
+                    debug!("Ignoring the card that is already playing");
+                    return Ok(());
+                }
+                RescanPolicy::TogglePause => Some(Card::Pause),
+                RescanPolicy::Restart => None,
+                RescanPolicy::Next => Some(Card::Next),
+            };
+            if let Some(control) = control {
+                self.play(&mut library_lock, None, control);
+                return Ok(());
+            }
+        }
+
+        if let Some(music_file) = card {
+            self.play(&mut library_lock, Some(card_id), music_file);
+        } else if self.pairing.is_some() {
+            self.pair(&mut library_lock, card_id);
+        } else {
+            info!("Unknown card");
+            self.prompter.play(&Prompt::CardUnknown);
+        }
+        Ok(())
+    }
+
+    fn on_card_removed(&mut self, card_id: Arc<str>) -> Result<(), Error> {
+        debug!("Card removed: {card_id}");
+        self.emit(Event::CardRemoved {
+            id: card_id.clone(),
+        });
+
+        let playing = self.player.track().is_some() && !self.player.sink().is_paused();
+        if self.config.playback.hold_mode && playing && self.current_card.as_ref() == Some(&card_id)
+        {
+            info!("Card lifted, pausing");
+            let library = self.library;
+            let mut library_lock = library.lock()?;
+            self.play(&mut library_lock, None, Card::Pause);
+            self.lifted = Some(card_id);
+        }
+        Ok(())
+    }
+
+    fn on_reader(&mut self, status: ReaderStatus) {
+        debug!("Card reader: {status:?}");
+        self.reader = status.clone();
+        self.emit(Event::ReaderChanged { status });
+    }
+
+    fn on_command(&mut self, command: Command) -> Result<(), Error> {
+        debug!("Command: {command:?}");
+
+        let library = self.library;
+        match command {
+            Command::Play(card_id) => {
+                let mut library_lock = library.lock()?;
+                match library_lock.get(&card_id).cloned() {
+                    Some(card) => self.play(&mut library_lock, Some(card_id), card),
+                    None => warn!("Cannot play unknown card: {card_id}"),
+                }
+            }
+            Command::Control(card) => {
+                let mut library_lock = library.lock()?;
+                self.play(&mut library_lock, None, card);
+            }
+            Command::SleepTimer {
+                after,
+                finish_track,
+            } => self.set_sleep_timer(after, finish_track),
+            Command::CancelSleepTimer => self.cancel_sleep_timer(),
+            Command::Announce(prompt) => self.prompter.play(&prompt),
+            Command::Status(tx) => {
+                if tx.send(self.status()).is_err() {
+                    error!("Failed to send status");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> Status {
+        let track = self.player.track();
+        Status {
+            card: track.as_ref().and(self.current_card.clone()),
+            track,
+            position: self.player.position(),
+            volume_db: self.volume.db(),
+            paused: self.player.sink().is_paused(),
+            pairing: self.pairing.is_some(),
+            sleep_remaining: self.sleep_remaining(),
+            reader: self.reader.clone(),
+        }
+    }
+
+    fn play(
+        &mut self,
+        library: &mut MutexGuard<'_, Library>,
+        card_id: Option<Arc<str>>,
+        music_file: Card,
+    ) {
+        info!("Playing: {music_file:?}");
+
+        let replaces_queue = matches!(
+            music_file,
+            Card::Play(_) | Card::Playlist(_) | Card::Audiobook(_) | Card::Shuffle
+        );
+        if replaces_queue {
+            // Keeps the current playback, e.g. a lullaby, running instead of interrupting it.
+            match card_id.as_ref() {
+                Some(id) if !self.is_allowed(id) => {
+                    info!("Card not allowed during quiet hours: {id}");
+                    self.emit(Event::CardBlocked { id: id.clone() });
+                    return;
+                }
+                // Shuffle only picks from the allowed cards.
+                None if !matches!(music_file, Card::Shuffle) && !self.allows_unlisted() => {
+                    info!("Playback not allowed during quiet hours");
+                    return;
+                }
+                _ => {}
+            }
+        }
+        let falling_asleep = matches!(
+            self.sleep_timer,
+            Some(SleepTimer::FinishingTrack { .. } | SleepTimer::FadingOut { .. })
+        );
+        if replaces_queue && falling_asleep {
+            // The new queue starts at full volume and would be paused right away.
+            info!("New queue while falling asleep, cancelling the sleep timer");
+            self.sleep_timer = None;
+            self.emit(Event::SleepTimerCancelled);
+        }
+        if let Some(resumable_id) = &self.resumable {
+            if replaces_queue || matches!(music_file, Card::Pause) {
+                store_bookmark(library, &self.config.library, resumable_id, &self.player);
+            }
+            if replaces_queue {
+                self.resumable = None;
+            }
+        }
+        if replaces_queue {
+            self.current_card.clone_from(&card_id);
+            self.queue_active = true;
+            self.lifted = None;
+        }
+
+        match music_file {
+            Card::Audiobook(music_file) => {
+                let bookmark = card_id.as_ref().and_then(|id| library.bookmark(id));
+                self.player.play_at(vec![music_file], bookmark);
+                self.resumable = card_id;
+            }
+            Card::Shuffle => match library.get_random(|id| self.is_allowed(id)) {
+                Some(card) => play_card(card, &mut self.player),
+                None => info!("No card to shuffle"),
+            },
+            Card::ToggleHotspot => self.toggle_manager(),
+            Card::Admin => self.admin_card(),
+            Card::SleepTimer(after) => self.set_sleep_timer(after, self.config.sleep.finish_track),
+            Card::VolumeUp => {
+                let before = self.effective_db();
+                // The ceiling only caps what is heard, so it is never stored as the volume.
+                if self.volume_ceiling().is_none_or(|max_db| before < max_db) {
+                    self.volume.up();
+                }
+                if self.effective_db() <= before {
+                    self.prompter.play(&Prompt::VolumeMax);
+                }
+                self.volume_changed(library);
+            }
+            Card::VolumeDown => {
+                // Steps down from what is heard, which may be below the stored volume.
+                self.volume.set_db(self.effective_db());
+                self.volume.down();
+                self.volume_changed(library);
+            }
+            card => play_card(&card, &mut self.player),
+        }
+    }
+
+    fn is_allowed(&self, card_id: &str) -> bool {
+        match self.parental.lock() {
+            Ok(parental) => parental.allows(card_id, Local::now().time()),
+            Err(_) => {
+                error!("Failed to lock parental limits");
+                true
+            }
+        }
+    }
+
+    fn allows_unlisted(&self) -> bool {
+        match self.parental.lock() {
+            Ok(parental) => parental.allows_unlisted(Local::now().time()),
+            Err(_) => {
+                error!("Failed to lock parental limits");
+                true
+            }
+        }
+    }
+
+    fn volume_ceiling(&self) -> Option<f32> {
+        match self.parental.lock() {
+            Ok(parental) => parental.max_db(Local::now().time()),
+            Err(_) => {
+                error!("Failed to lock parental limits");
+                None
+            }
+        }
+    }
+
+    /// Returns the volume capped by the parental limits.
+    fn effective_db(&self) -> f32 {
+        match self.volume_ceiling() {
+            Some(max_db) => self.volume.db().min(max_db),
+            None => self.volume.db(),
+        }
+    }
+
+    /// Applies the volume, capped by the parental limits, if it differs from the last one.
+    ///
+    /// A level the mixer failed to set is reported once and not tried again.
+    fn apply_volume(&mut self) {
+        match self.levels.apply(self.effective_db()) {
+            Ok(None) => {}
+            Ok(Some(gain)) => {
+                self.player.set_gain(gain);
+                self.prompter.set_gain(gain);
+            }
+            Err(err) => {
+                error!("Failed to set volume: {err}");
+                self.emit_error(format!("Failed to set volume: {err}"));
+            }
+        }
+    }
+
+    fn volume_changed(&mut self, library: &mut Library) {
+        self.apply_volume();
+        info!("Volume: {} dB", self.volume.db());
+        library.set_volume_db(self.volume.db());
+        if let Err(err) = library.save_to_file(&self.config.library) {
+            error!("Failed to save volume: {err}");
+        }
+        self.emit(Event::VolumeChanged {
+            volume_db: self.volume.db(),
+        });
+    }
+
+    fn toggle_manager(&mut self) {
+        if let Err(err) = toggle_hotspot(&self.config.hotspot, !self.hotspot_enabled) {
+            self.prompter.play(&Prompt::Failure);
+            self.emit_error(format!("Failed to toggle hotspot: {err}"));
+        } else if self.hotspot_enabled {
+            self.prompter.play(&Prompt::HotspotOff);
+        } else {
+            self.prompter.play(&Prompt::HotspotOn {
+                ssid: self.config.hotspot.ssid.clone(),
+            });
+        }
+        if self.hotspot_enabled {
+            match self.tx_manager_shutdown.send(()) {
+                Ok(()) => info!("Sent shutdown message"),
+                Err(err) => error!("Failed to send shutdown message: {err}"),
+            }
+        } else {
+            let state = manager::AppState {
+                tx_pairing: self.tx_pairing.clone(),
+                tx_command: self.tx_command.clone(),
+                events: self.events.clone(),
+                parental: self.parental.clone(),
+                config_path: self.config.path.clone(),
+                library: self.library.clone(),
+                library_path: self.config.library.clone(),
+            };
+            let rx_manager_shutdown_clone = self.rx_manager_shutdown.clone();
+            let listen = self.config.manager.listen.clone();
+            std::thread::spawn(move || {
+                match manager::serve(&listen, state, rx_manager_shutdown_clone) {
+                    Ok(()) => info!("Manager started"),
+                    Err(err) => error!("Failed to start manager: {err}"),
+                }
+            });
+        }
+        self.hotspot_enabled = !self.hotspot_enabled;
+    }
+
+    fn pair(&mut self, library: &mut MutexGuard<'_, Library>, card_id: Arc<str>) {
+        info!("Read card: {card_id}");
+        let Some(pairing) = self.pairing.as_mut() else {
+            return;
+        };
+        let scans = pairing.scan(card_id.clone());
+        let required = self.config.pairing.confirmations;
+        if scans < required {
+            self.prompter.play(&Prompt::PairingScan);
+            self.emit(Event::PairingProgress {
+                id: card_id,
+                left: required - scans,
+            });
+            return;
+        }
+
+        let target = pairing.target().cloned();
+        if target.is_none() && library.get(&card_id).is_some() {
+            // Keeps the binding of a reassigned card so it can be edited in the manager.
+            self.prompter.play(&Prompt::Success);
+            info!("Selected card: {card_id}");
+            self.emit(Event::CardSelected { id: card_id });
+        } else if self.bind_card(library, &card_id, target.clone()) {
+            self.prompter.play(&Prompt::Success);
+            info!("Added card to library: {card_id} -> {target:?}");
+            self.emit(Event::CardPaired { id: card_id });
+        }
+        self.pairing = None;
+        self.emit(Event::PairingToggled { enabled: false });
+    }
+
+    fn admin_card(&mut self) {
+        match self.admin.as_mut() {
+            Some(admin) => admin.cycle(),
+            None => {
+                let window = Duration::from_secs(self.config.admin.window_seconds);
+                let Some(admin) = Admin::new(window) else {
+                    warn!("Admin window too long: {window:?}");
+                    return;
+                };
+                info!("Admin mode enabled");
+                self.admin = Some(admin);
+                self.emit(Event::AdminToggled { enabled: true });
+            }
+        }
+        if let Some(action) = self.admin.as_ref().map(|admin| admin.action().clone()) {
+            info!("Admin action: {action:?}");
+            self.prompter.play(&Prompt::Admin(action.clone()));
+            self.emit(Event::AdminActionSelected { action });
+        }
+    }
+
+    fn poll_admin(&mut self) {
+        if self.admin.as_ref().is_some_and(Admin::is_expired) {
+            info!("Admin mode timed out");
+            self.admin = None;
+            self.prompter.play(&Prompt::AdminTimedOut);
+            self.emit(Event::AdminToggled { enabled: false });
+        }
+    }
+
+    /// Applies the selected admin action to the scanned card and leaves admin mode.
+    fn administer(&mut self, library: &mut MutexGuard<'_, Library>, card_id: Arc<str>) {
+        let Some(admin) = self.admin.take() else {
+            return;
+        };
+        self.emit(Event::AdminToggled { enabled: false });
+        match admin.action().clone() {
+            AdminAction::Enroll => {
+                if library.get(&card_id).is_some() {
+                    info!("Cannot enroll bound card: {card_id}");
+                    self.prompter.play(&Prompt::Failure);
+                    return;
+                }
+                // The last card stays current after its queue ended, but only a playing one
+                // is enrolled.
+                let target = self
+                    .current_card
+                    .as_ref()
+                    .filter(|_| self.player.track().is_some())
+                    .and_then(|id| library.get(id).cloned());
+                if self.bind_card(library, &card_id, target.clone()) {
+                    self.prompter.play(&Prompt::Success);
+                    info!("Enrolled card: {card_id} -> {target:?}");
+                    self.emit(Event::CardPaired { id: card_id });
+                }
+            }
+            AdminAction::Delete => {
+                if !library.contains(&card_id) {
+                    info!("Cannot delete unknown card: {card_id}");
+                    self.prompter.play(&Prompt::Failure);
+                    return;
+                }
+                if let Err(err) = library.remove_and_save(&card_id, &self.config.library) {
+                    error!("Failed to save library: {err}");
+                    self.prompter.play(&Prompt::Failure);
+                    self.emit_error(format!("Failed to save library: {err}"));
+                } else {
+                    self.prompter.play(&Prompt::Success);
+                    info!("Deleted card: {card_id}");
+                    if self.resumable.as_ref() == Some(&card_id) {
+                        self.resumable = None;
+                    }
+                    self.emit(Event::CardDeleted { id: card_id });
+                }
+            }
+            AdminAction::Control(card) => {
+                if self.bind_card(library, &card_id, Some(card.clone())) {
+                    self.prompter.play(&Prompt::Success);
+                    info!("Made card a control card: {card_id} -> {card:?}");
+                    self.emit(Event::CardPaired { id: card_id });
+                }
+            }
+        }
+    }
+
+    /// Binds `card_id` to `card` and saves the library, undoing the change if that fails.
+    ///
+    /// Returns whether the card was saved.
+    fn bind_card(&mut self, library: &mut Library, card_id: &str, card: Option<Card>) -> bool {
+        match library.update_and_save(card_id, card, &self.config.library) {
+            Ok(()) => true,
+            Err(err) => {
+                error!("Failed to save library: {err}");
+                self.prompter.play(&Prompt::Failure);
+                self.emit_error(format!("Failed to save library: {err}"));
+                false
+            }
+        }
+    }
 }
 
 /// Runs the service.
 ///
 /// # Arguments
 ///
-/// * `rx` - The receiver channel.
+/// * `config` - The configuration.
 /// * `library` - The library.
+/// * `tx_manager_shutdown` - The sender used to shut down the manager.
+/// * `rx_manager_shutdown` - The receiver the manager listens on for shutdown signals.
+/// * `reader` - The card reader, run on its own thread. The service keeps running after it ends.
 ///
 /// # Errors
 ///
 /// Returns an error if there is an issue running the service.
 pub fn run(
+    config: &config::Config,
     library: &Arc<Mutex<Library>>,
     tx_manager_shutdown: Sender<()>,
     rx_manager_shutdown: Receiver<()>,
-    rx: &Receiver<Arc<str>>,
+    mut reader: impl CardReader + Send + 'static,
 ) -> Result<(), Error> {
-    let tx_manager_shutdown = Arc::from(tx_manager_shutdown);
-    let rx_manager_shutdown = Arc::from(rx_manager_shutdown);
+    let (tx, mut rx) = crossbeam_channel::bounded(10);
+    let mut reader_handle = Some(std::thread::spawn(move || {
+        if let Err(err) = reader.read(&tx) {
+            error!("Card reader failed: {err}");
+        }
+    }));
 
     let (_stream, stream_handle) = OutputStream::try_default().map_err(Error::from)?;
-    let sink = Sink::try_new(&stream_handle)?;
 
-    let (tx_pairing, rx_pairing): (Sender<()>, Receiver<()>) = crossbeam_channel::bounded(1);
-    let tx_pairing = Arc::from(tx_pairing);
-    let mut pairing_cards: Vec<Arc<str>> = vec![];
+    let (tx_pairing, rx_pairing): (Sender<PairingRequest>, Receiver<PairingRequest>) =
+        crossbeam_channel::bounded(1);
+    let (tx_command, rx_command): (Sender<Command>, Receiver<Command>) =
+        crossbeam_channel::bounded(10);
+
+    let volume = Volume::new(config.volume.clone(), library.lock()?.volume_db());
+    let mixer: Box<dyn Mixer> = match &config.volume.backend {
+        VolumeBackend::Software => Box::new(SoftwareMixer),
+        VolumeBackend::Alsa { device, control } => match AlsaMixer::new(device, control) {
+            Ok(mixer) => Box::new(mixer),
+            Err(err) => {
+                error!("Failed to open ALSA mixer, falling back to software volume: {err}");
+                Box::new(SoftwareMixer)
+            }
+        },
+    };
 
-    let mut hotspot_enabled = false;
-    let mut is_pairing = false;
+    let mut service = Service {
+        config,
+        library,
+        player: Player::new(&stream_handle, config.fade.clone())?,
+        prompter: Prompter::new(&stream_handle, config.sounds.clone())?,
+        ducked: false,
+        volume,
+        levels: Levels::new(mixer),
+        parental: Arc::new(Mutex::new(config.parental.clone())),
+        tx_pairing: Arc::from(tx_pairing),
+        tx_command: Arc::from(tx_command),
+        tx_manager_shutdown: Arc::from(tx_manager_shutdown),
+        rx_manager_shutdown: Arc::from(rx_manager_shutdown),
+        events: broadcast::channel(32).0,
+        last_track: None,
+        hotspot_enabled: false,
+        pairing: None,
+        admin: None,
+        current_card: None,
+        resumable: None,
+        lifted: None,
+        reader: ReaderStatus::Searching,
+        queue_active: false,
+        sleep_timer: None,
+    };
+    service.apply_volume();
 
     loop {
-        match rx_pairing.try_recv() {
-            Ok(()) => {
-                is_pairing = !is_pairing;
-                if is_pairing {
-                    info!("Pairing mode enabled");
-                    pairing_cards.clear();
+        service.poll_player();
+        service.poll_prompts();
+        service.apply_volume();
+        service.poll_sleep_timer()?;
+        service.poll_pairing();
+        service.poll_admin();
+        // Only wake up periodically while a track is playing or a timer is running.
+        let tick = service
+            .next_wakeup()
+            .map_or_else(crossbeam_channel::never, crossbeam_channel::after);
+        crossbeam_channel::select! {
+            recv(rx) -> event => match event {
+                Ok(CardEvent::Present(card_id)) => service.on_card(card_id)?,
+                Ok(CardEvent::Removed(card_id)) => service.on_card_removed(card_id)?,
+                Ok(CardEvent::Reader(status)) => service.on_reader(status),
+                Err(_) => {
+                    // Keeps playing and serving the manager without cards, e.g. after a script.
+                    info!("Card reader stopped");
+                    rx = crossbeam_channel::never();
+                    if reader_handle.take().is_some_and(|handle| handle.join().is_err()) {
+                        error!("Card reader thread panicked");
+                    }
                 }
-            }
-            Err(e) => match e {
-                crossbeam_channel::TryRecvError::Empty => {}
-                crossbeam_channel::TryRecvError::Disconnected => unreachable!(),
             },
-        }
-        match rx.try_recv() {
-            Ok(card_id) => {
-                debug!("Card ID: {card_id}");
-
-                let mut library_lock = library.lock()?;
-                let card = library_lock.get(&card_id);
-
-                if let Some(music_file) = card {
-                    info!("Playing: {music_file:?}");
-
-                    match music_file {
-                        Card::Shuffle => {
-                            let card = library_lock.get_random();
-                            if let Some(card) = card {
-                                play_card(card, &sink);
-                            }
-                        }
-                        Card::ToggleHotspot => {
-                            if toggle_hotspot(!hotspot_enabled).is_err() {
-                                play_sound(&sink, FAILURE_SOUND);
-                            }
-                            if hotspot_enabled {
-                                match tx_manager_shutdown.send(()) {
-                                    Ok(()) => info!("Sent shutdown message"),
-                                    Err(err) => error!("Failed to send shutdown message: {err}"),
-                                }
-                            } else {
-                                let tx_pairing_clone = tx_pairing.clone();
-                                let rx_manager_shutdown_clone = rx_manager_shutdown.clone();
-                                std::thread::spawn(move || {
-                                    match manager::serve(
-                                        "0.0.0.0:8080",
-                                        tx_pairing_clone,
-                                        rx_manager_shutdown_clone,
-                                    ) {
-                                        Ok(()) => info!("Manager started"),
-                                        Err(err) => error!("Failed to start manager: {err}"),
-                                    }
-                                });
-                            }
-                            hotspot_enabled = !hotspot_enabled;
-                        }
-                        card => play_card(card, &sink),
-                    }
-                } else if is_pairing {
-                    info!("Read card: {card_id}");
-                    pairing_cards.push(card_id);
-                    if let Some(most_common_card) = pairing_cards
-                        .iter()
-                        .find(|&card| pairing_cards.iter().filter(|&c| *c == *card).count() >= 3)
-                    {
-                        library_lock.add(most_common_card);
-                        if let Err(err) = library_lock.save_to_file("music.json") {
-                            library_lock.remove(most_common_card);
-                            error!("Failed to save library: {err}");
-                            play_sound(&sink, FAILURE_SOUND);
-                        } else {
-                            play_sound(&sink, SUCCESS_SOUND);
-                            info!("Added card to library: {most_common_card}");
-                        }
-                        is_pairing = !is_pairing;
-                    }
-                } else {
-                    info!("Unknown card");
+            recv(rx_pairing) -> request => {
+                if let Ok(request) = request {
+                    service.on_pairing_request(request);
                 }
-                drop(library_lock);
             }
-            Err(e) => match e {
-                crossbeam_channel::TryRecvError::Empty => continue,
-                crossbeam_channel::TryRecvError::Disconnected => break,
-            },
+            recv(rx_command) -> command => {
+                if let Ok(command) = command {
+                    service.on_command(command)?;
+                }
+            }
+            recv(tick) -> _ => {}
         }
     }
-    Ok(())
 }
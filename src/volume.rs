@@ -0,0 +1,105 @@
+use crate::config::VolumeConfig;
+
+/// The playback volume in decibels, bounded by the configured range.
+#[derive(Debug, Clone)]
+pub struct Volume {
+    db: f32,
+    config: VolumeConfig,
+}
+
+impl Volume {
+    /// Creates a volume starting at `db`, or at the configured default if there is none.
+    #[must_use]
+    pub fn new(config: VolumeConfig, db: Option<f32>) -> Self {
+        let mut volume = Self {
+            db: config.default_db,
+            config,
+        };
+        if let Some(db) = db {
+            volume.set_db(db);
+        }
+        volume
+    }
+
+    #[must_use]
+    pub const fn db(&self) -> f32 {
+        self.db
+    }
+
+    pub fn set_db(&mut self, db: f32) {
+        self.db = db.clamp(self.config.min_db, self.config.max_db);
+    }
+
+    pub fn up(&mut self) {
+        self.set_db(self.db + self.config.step_db);
+    }
+
+    pub fn down(&mut self) {
+        self.set_db(self.db - self.config.step_db);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> VolumeConfig {
+        VolumeConfig {
+            min_db: -30.0,
+            max_db: -6.0,
+            step_db: 4.0,
+            default_db: -18.0,
+            ..VolumeConfig::default()
+        }
+    }
+
+    #[test]
+    fn starts_at_the_default_without_a_stored_level() {
+        assert_eq!(Volume::new(config(), None).db(), -18.0);
+    }
+
+    #[test]
+    fn restores_a_stored_level() {
+        assert_eq!(Volume::new(config(), Some(-10.0)).db(), -10.0);
+    }
+
+    #[test]
+    fn clamps_a_stored_level_outside_the_range() {
+        assert_eq!(Volume::new(config(), Some(0.0)).db(), -6.0);
+        assert_eq!(Volume::new(config(), Some(-60.0)).db(), -30.0);
+    }
+
+    #[test]
+    fn steps_up_and_down() {
+        let mut volume = Volume::new(config(), None);
+        volume.up();
+        assert_eq!(volume.db(), -14.0);
+        volume.down();
+        volume.down();
+        assert_eq!(volume.db(), -22.0);
+    }
+
+    #[test]
+    fn steps_stop_at_the_bounds() {
+        let mut volume = Volume::new(config(), Some(-8.0));
+        volume.up();
+        assert_eq!(volume.db(), -6.0);
+        volume.up();
+        assert_eq!(volume.db(), -6.0);
+
+        let mut volume = Volume::new(config(), Some(-28.0));
+        volume.down();
+        assert_eq!(volume.db(), -30.0);
+        volume.down();
+        assert_eq!(volume.db(), -30.0);
+    }
+
+    #[test]
+    fn set_db_clamps() {
+        let mut volume = Volume::new(config(), None);
+        volume.set_db(3.0);
+        assert_eq!(volume.db(), -6.0);
+        volume.set_db(f32::NEG_INFINITY);
+        assert_eq!(volume.db(), -30.0);
+    }
+}